@@ -0,0 +1,635 @@
+use std::{collections::HashMap, fmt};
+
+use crate::isa::{register_from_name, AluOp, DataType, FlagCondition, Instruction, INSTRUCTIONS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+/// Something wrong (or suspicious) in the source, pointing at the words that caused it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// 1 based line number
+    pub line: usize,
+    /// 1 based column, counted in chars
+    pub column: usize,
+    /// how many chars to underline, at least 1
+    pub len: usize,
+    /// the whole line as it is in the source, comments included
+    pub source_line: String
+}
+
+impl Diagnostic {
+    /// Render the diagnostic with a caret underlined excerpt, `file_name` goes in the location line
+    pub fn render(&self, file_name: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+        let line_number = self.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        format!(
+            "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            severity,
+            self.message,
+            gutter,
+            file_name,
+            self.line,
+            self.column,
+            gutter,
+            line_number,
+            self.source_line,
+            gutter,
+            " ".repeat(self.column - 1),
+            "^".repeat(self.len.max(1))
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render("<source>"))
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// A successfully assembled program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// binary image that starts at address 0
+    pub bin: Vec<u8>,
+    pub warnings: Vec<Diagnostic>
+}
+
+/// A word of source and the column it starts at
+#[derive(Debug, Clone, Copy)]
+struct Word<'a> {
+    text: &'a str,
+    column: usize
+}
+
+/// One line of source being parsed word by word
+struct Line<'a> {
+    number: usize,
+    source: &'a str,
+    words: Vec<Word<'a>>,
+    next: usize
+}
+
+impl<'a> Line<'a> {
+    /// `code` is the line with its comment removed, `source` is the line as written
+    fn new(number: usize, source: &'a str, code: &'a str) -> Line<'a> {
+        let mut words = Vec::new();
+        let mut start = None;
+
+        for (column, (byte, char)) in code.char_indices().enumerate() {
+            match (char.is_whitespace(), start) {
+                (false, None) => start = Some((byte, column)),
+                (true, Some((start_byte, start_column))) => {
+                    words.push(Word { text: &code[start_byte..byte], column: start_column + 1 });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((start_byte, start_column)) = start {
+            words.push(Word { text: &code[start_byte..], column: start_column + 1 });
+        }
+
+        Line {
+            number,
+            source,
+            words,
+            next: 0
+        }
+    }
+
+    fn diagnostic(&self, severity: Severity, column: usize, len: usize, message: String) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message,
+            line: self.number,
+            column,
+            len,
+            source_line: self.source.to_string()
+        }
+    }
+
+    fn error(&self, word: Word, message: String) -> Diagnostic {
+        self.diagnostic(Severity::Error, word.column, word.text.chars().count(), message)
+    }
+
+    fn warning(&self, word: Word, message: String) -> Diagnostic {
+        self.diagnostic(Severity::Warning, word.column, word.text.chars().count(), message)
+    }
+
+    /// The next word, `what` describes it in the error if the line ends early
+    fn next(&mut self, what: &str) -> Result<Word<'a>, Diagnostic> {
+        match self.words.get(self.next) {
+            Some(word) => {
+                self.next += 1;
+                Ok(*word)
+            }
+            None => {
+                let end = self.words.last().map_or(1, |word| word.column + word.text.chars().count());
+                Err(self.diagnostic(Severity::Error, end, 1, format!("expected {} after `{}`", what, self.words[0].text)))
+            }
+        }
+    }
+
+    /// Whether anything follows the mnemonic
+    fn has_operands(&self) -> bool {
+        self.words.len() > 1
+    }
+
+    fn next_optional(&mut self) -> Option<Word<'a>> {
+        let word = self.words.get(self.next).copied();
+        self.next += word.is_some() as usize;
+        word
+    }
+
+    fn register(&mut self) -> Result<u8, Diagnostic> {
+        let word = self.next("a register")?;
+        register_from_name(word.text).ok_or_else(|| self.error(word, format!("unknown register `{}`", word.text)))
+    }
+
+    fn data_type(&mut self) -> Result<DataType, Diagnostic> {
+        let word = self.next("a type (byte, dbyte, qbyte or obyte)")?;
+        DataType::from_name(word.text).ok_or_else(|| self.error(word, format!("unknown type `{}`, expected byte, dbyte, qbyte or obyte", word.text)))
+    }
+
+    /// A number that has to fit in `value_type`, negative numbers become two's complement
+    fn value(&self, word: Word, value_type: DataType) -> Result<u64, Diagnostic> {
+        let (negative, digits) = match word.text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, word.text)
+        };
+        let value = parse_number(digits).ok_or_else(|| self.error(word, format!("`{}` is not a number", word.text)))?;
+
+        // the most negative number is one further from 0 than the most positive one
+        match negative {
+            false if value <= value_type.max_value() => Ok(value),
+            true if value <= value_type.max_value() / 2 + 1 => Ok(value.wrapping_neg() & value_type.max_value()),
+            _ => Err(self.error(word, format!("{} doesn't fit in a {}", word.text, value_type.name())))
+        }
+    }
+
+    fn address(&self, word: Word) -> Result<u64, Diagnostic> {
+        parse_number(word.text).ok_or_else(|| self.error(word, format!("`{}` is not an address", word.text)))
+    }
+
+    /// Make sure nothing is left on the line
+    fn finish(&self) -> Result<(), Diagnostic> {
+        match self.words.get(self.next) {
+            Some(word) => Err(self.error(*word, format!("unexpected `{}`", word.text))),
+            None => Ok(())
+        }
+    }
+}
+
+/// A decimal number, or hex if it starts with `0x`
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok()
+    }
+}
+
+/// A spot in the output where the address of a label has to go once it is known
+struct Relocation {
+    label: String,
+    offset: usize,
+    /// where the label is used, for the error if it's never declared
+    line: usize,
+    column: usize
+}
+
+/// Where a label was declared
+struct Label {
+    address: usize,
+    line: usize,
+    column: usize,
+    used: bool
+}
+
+/// Assemble source code into a binary image that starts at address 0
+///
+/// errors don't stop the assembler, it skips the line and carries on so every problem
+/// gets reported at once, in which case all diagnostics come back in source order
+pub fn assemble(asm_code_raw: &str) -> Result<Assembly, Vec<Diagnostic>> {
+    // pass 1, remove comments
+    let mut asm_code_pass1 = String::new();
+    let mut comment = false;
+    for char in asm_code_raw.chars() {
+        if char == ';' {
+            comment = true;
+        } else if comment {
+            if char == '\n' {
+                comment = false;
+                asm_code_pass1.push('\n');
+            }
+        } else {
+            asm_code_pass1.push(char);
+        }
+    }
+
+    // pass 2, assemble
+    let source_lines: Vec<&str> = asm_code_raw.lines().collect();
+    let mut found_labels: HashMap<String, Label> = HashMap::new();
+    let mut mentioned_labels: Vec<Relocation> = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut out = Vec::new();
+
+    for (i, code) in asm_code_pass1.lines().enumerate() {
+        let mut line = Line::new(i + 1, source_lines[i], code);
+
+        let word = match line.next_optional() {
+            Some(word) => word,
+            None => continue
+        };
+
+        let result = match word.text {
+            "move" => move_instruction(&mut line, &mut out),
+            "read" => read_instruction(&mut line, &mut out),
+            "write" => write_instruction(&mut line, &mut out),
+            "push" => push_instruction(&mut line, &mut out),
+            "pop" => pop_instruction(&mut line, &mut out),
+            "jump" => jump_instruction(&mut line, None, &mut mentioned_labels, &mut out),
+            "call" => call_instruction(&mut line, &mut mentioned_labels, &mut out),
+            "vectors" => vectors_instruction(&mut line, &mut mentioned_labels, &mut out),
+            "syscall" => line.next("a syscall number").and_then(|number| line.value(number, DataType::Byte)).map(|number| {
+                emit(Instruction::Syscall { number: number as u8 }, &mut out);
+            }),
+            "byte" | "dbyte" | "qbyte" | "obyte" => data(&mut line, DataType::from_name(word.text).unwrap(), &mut mentioned_labels, &mut out),
+            "not" if line.has_operands() => unary_instruction(&mut line, |dst, src| Instruction::NotRegister { dst, src }, &mut out),
+            "neg" => unary_instruction(&mut line, |dst, src| Instruction::Neg { dst, src }, &mut out),
+            text if line.has_operands() && AluOp::from_name(text).is_some() => alu_instruction(&mut line, AluOp::from_name(text).unwrap(), &mut out),
+            text if FlagCondition::from_mnemonic(text).is_some() => {
+                jump_instruction(&mut line, FlagCondition::from_mnemonic(text), &mut mentioned_labels, &mut out)
+            }
+            text if text.starts_with(':') => declare_label(&line, word, &mut found_labels, out.len()),
+            text => match no_operand_instruction(text) {
+                Some(instruction) => {
+                    emit(instruction, &mut out);
+                    Ok(())
+                }
+                None => {
+                    diagnostics.push(line.warning(word, format!("unknown instruction `{}`, the line is ignored", text)));
+                    continue;
+                }
+            }
+        };
+
+        if let Err(diagnostic) = result.and_then(|_| line.finish()) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    // pass 3, fill in label addresses
+    for mentioned_label in mentioned_labels {
+        match found_labels.get_mut(&mentioned_label.label) {
+            Some(label) => {
+                label.used = true;
+                out[mentioned_label.offset..mentioned_label.offset + 8].copy_from_slice(&(label.address as u64).to_be_bytes());
+            }
+            None => {
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    message: format!("label `{}` is used but never declared", mentioned_label.label),
+                    line: mentioned_label.line,
+                    column: mentioned_label.column,
+                    len: mentioned_label.label.chars().count(),
+                    source_line: source_lines[mentioned_label.line - 1].to_string()
+                });
+            }
+        }
+    }
+
+    for (name, label) in &found_labels {
+        if !label.used {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                message: format!("label `{}` is never used", name),
+                line: label.line,
+                column: label.column,
+                len: name.chars().count() + 1,
+                source_line: source_lines[label.line - 1].to_string()
+            });
+        }
+    }
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        Err(diagnostics)
+    } else {
+        Ok(Assembly {
+            bin: out,
+            warnings: diagnostics
+        })
+    }
+}
+
+fn declare_label(line: &Line, word: Word, found_labels: &mut HashMap<String, Label>, address: usize) -> Result<(), Diagnostic> {
+    let name = &word.text[1..];
+
+    if name.is_empty() {
+        return Err(line.error(word, "a label needs a name right after ':'".to_string()));
+    }
+
+    if let Some(label) = found_labels.get(name) {
+        return Err(line.error(word, format!("label `{}` is already declared on line {}", name, label.line)));
+    }
+
+    found_labels.insert(name.to_string(), Label {
+        address,
+        line: line.number,
+        column: word.column,
+        used: false
+    });
+
+    Ok(())
+}
+
+/// `<type> <value>`, an obyte can also be a label so tables of addresses can be written
+fn data(line: &mut Line, value_type: DataType, mentioned_labels: &mut Vec<Relocation>, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let value = line.next("a value")?;
+
+    if value_type == DataType::OByte && !value.text.starts_with(|char: char| char.is_ascii_digit() || char == '-') {
+        mentioned_labels.push(Relocation {
+            label: value.text.to_string(),
+            offset: out.len(),
+            line: line.number,
+            column: value.column
+        });
+        out.extend_from_slice(&[0; 8]);
+
+        return Ok(());
+    }
+
+    let value = line.value(value, value_type)?;
+    out.extend_from_slice(&value.to_be_bytes()[8 - value_type.size()..]);
+
+    Ok(())
+}
+
+fn emit(instruction: Instruction, out: &mut Vec<u8>) {
+    out.extend_from_slice(&instruction.encode());
+}
+
+/// Instructions like `add` or `halt` that are just the mnemonic
+fn no_operand_instruction(word: &str) -> Option<Instruction> {
+    let def = INSTRUCTIONS.iter().find(|def| def.mnemonic == word && def.operands.is_empty())?;
+
+    Instruction::from_operands(def.opcode, &[])
+}
+
+fn move_instruction(line: &mut Line, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let type_or_register = line.next("a type or register")?;
+
+    if let Some(dst) = register_from_name(type_or_register.text) {
+        let src = line.register()?;
+
+        emit(Instruction::MoveRegister { dst, src }, out);
+        return Ok(());
+    }
+
+    let ty = DataType::from_name(type_or_register.text).ok_or_else(|| {
+        line.error(type_or_register, format!("`{}` is not a register or a type (byte, dbyte, qbyte or obyte)", type_or_register.text))
+    })?;
+    let register = line.register()?;
+
+    let value = line.next("a value")?;
+    let mut chars = value.text.chars();
+    let value = match (ty, chars.next(), chars.next()) {
+        // a byte can also be given as a char
+        (DataType::Byte, Some(char), None) if !char.is_ascii_digit() && char.is_ascii() => char as u64,
+        _ => line.value(value, ty)?
+    };
+
+    emit(Instruction::MoveValue { ty, register, value }, out);
+    Ok(())
+}
+
+fn read_instruction(line: &mut Line, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let type_word = line.next("a type (byte, dbyte, qbyte, obyte, sbyte, sdbyte or sqbyte)")?;
+
+    // sbyte, sdbyte and sqbyte sign extend what they read, sobyte is the same as obyte
+    let signed_type = [DataType::Byte, DataType::DByte, DataType::QByte, DataType::OByte].into_iter().find(|ty| ty.signed_name() == type_word.text);
+    let ty = match signed_type.or_else(|| DataType::from_name(type_word.text)) {
+        Some(ty) => ty,
+        None => {
+            return Err(line.error(type_word, format!("unknown type `{}`, expected byte, dbyte, qbyte, obyte, sbyte, sdbyte or sqbyte", type_word.text)));
+        }
+    };
+    let register = line.register()?;
+
+    let register_or_address = line.next("an address or register")?;
+    let instruction = match (register_from_name(register_or_address.text), signed_type.is_some()) {
+        (Some(address_register), false) => Instruction::ReadRegister { ty, register, address_register },
+        (Some(address_register), true) => Instruction::ReadSignedRegister { ty, register, address_register },
+        (None, false) => Instruction::ReadAddress { ty, register, address: line.address(register_or_address)? },
+        (None, true) => Instruction::ReadSignedAddress { ty, register, address: line.address(register_or_address)? }
+    };
+
+    emit(instruction, out);
+    Ok(())
+}
+
+fn write_instruction(line: &mut Line, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let ty = line.data_type()?;
+    let register = line.register()?;
+
+    let register_or_address = line.next("an address or register")?;
+    if let Some(address_register) = register_from_name(register_or_address.text) {
+        emit(Instruction::WriteRegister { ty, register, address_register }, out);
+    } else {
+        let address = line.address(register_or_address)?;
+
+        emit(Instruction::WriteAddress { ty, register, address }, out);
+    }
+
+    Ok(())
+}
+
+fn push_instruction(line: &mut Line, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let ty = line.data_type()?;
+
+    let register_or_value = line.next("a value or register")?;
+    if let Some(register) = register_from_name(register_or_value.text) {
+        emit(Instruction::PushRegister { ty, register }, out);
+    } else {
+        let value = line.value(register_or_value, ty)?;
+
+        emit(Instruction::PushValue { ty, value }, out);
+    }
+
+    Ok(())
+}
+
+fn pop_instruction(line: &mut Line, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let ty = line.data_type()?;
+    let register = line.register()?;
+
+    emit(Instruction::Pop { ty, register }, out);
+    Ok(())
+}
+
+/// `not <dst> <src>` or `neg <dst> <src>`
+fn unary_instruction(line: &mut Line, instruction: fn(u8, u8) -> Instruction, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let dst = line.register()?;
+    let src = line.register()?;
+
+    emit(instruction(dst, src), out);
+    Ok(())
+}
+
+/// `<op> <dst> <src1> <src2>` or `<op> <type> <dst> <src> <value>`, the type can be left out
+/// and then it's the smallest one the value fits in
+fn alu_instruction(line: &mut Line, op: AluOp, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let type_or_register = line.next("a type or register")?;
+
+    let (ty, dst) = match DataType::from_name(type_or_register.text) {
+        Some(ty) => (Some(ty), line.register()?),
+        None => {
+            let dst = register_from_name(type_or_register.text).ok_or_else(|| {
+                line.error(type_or_register, format!("`{}` is not a register or a type (byte, dbyte, qbyte or obyte)", type_or_register.text))
+            })?;
+
+            (None, dst)
+        }
+    };
+    let src = line.register()?;
+
+    let register_or_value = line.next("a value or register")?;
+    match (ty, register_from_name(register_or_value.text)) {
+        (None, Some(src2)) => emit(Instruction::AluRegister { op, dst, src1: src, src2 }, out),
+        (Some(_), Some(_)) => {
+            return Err(line.error(register_or_value, format!("expected a value after the type, found register `{}`", register_or_value.text)));
+        }
+        // the value is zero extended before the op, so a negative one only works as an obyte
+        (Some(ty), None) if ty != DataType::OByte && register_or_value.text.starts_with('-') => {
+            return Err(line.error(register_or_value, format!(
+                "{} would be zero extended from a {} and lose its sign, leave out the type or use obyte",
+                register_or_value.text,
+                ty.name()
+            )));
+        }
+        (ty, None) => {
+            let value = line.value(register_or_value, ty.unwrap_or(DataType::OByte))?;
+            let ty = ty.unwrap_or_else(|| DataType::smallest_for(value));
+
+            emit(Instruction::AluValue { op, ty, dst, src, value }, out);
+        }
+    }
+
+    Ok(())
+}
+
+/// What a jump or call goes to
+enum Target<'a> {
+    Register(u8),
+    Address(u64),
+    Label(Word<'a>)
+}
+
+impl<'a> Line<'a> {
+    fn target(&mut self) -> Result<Target<'a>, Diagnostic> {
+        let word = self.next("a label, address or register")?;
+
+        if let Some(register) = register_from_name(word.text) {
+            Ok(Target::Register(register))
+        } else if word.text.starts_with(|char: char| char.is_ascii_digit()) {
+            Ok(Target::Address(self.address(word)?))
+        } else {
+            // anything that doesn't start with a digit is a label
+            Ok(Target::Label(word))
+        }
+    }
+}
+
+/// Emit an instruction whose address is `label`, it gets address 0 for now and is filled in by pass 3
+fn emit_with_label(instruction: Instruction, label: Word, line: &Line, mentioned_labels: &mut Vec<Relocation>, out: &mut Vec<u8>) {
+    mentioned_labels.push(Relocation {
+        label: label.text.to_string(),
+        offset: out.len() + instruction.address_offset().unwrap(),
+        line: line.number,
+        column: label.column
+    });
+
+    emit(instruction, out);
+}
+
+/// What a conditional jump tests
+enum JumpCondition {
+    /// the value in c is 1 (true) or 0 (false)
+    Register(bool),
+    Flags(FlagCondition)
+}
+
+/// `jump <target> [true/false/<flag condition>]`, or `j<flag condition> <target>` when `flags` is given
+fn jump_instruction(line: &mut Line, flags: Option<FlagCondition>, mentioned_labels: &mut Vec<Relocation>, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    let target = line.target()?;
+
+    let condition = match flags {
+        Some(condition) => Some(JumpCondition::Flags(condition)),
+        None => match line.next_optional() {
+            Some(word) => match word.text {
+                "true" => Some(JumpCondition::Register(true)),
+                "false" => Some(JumpCondition::Register(false)),
+                text => match FlagCondition::from_name(text) {
+                    Some(condition) => Some(JumpCondition::Flags(condition)),
+                    None => {
+                        return Err(line.error(word, format!("expected true, false or a flag condition (z, nz, lt, ...), found `{}`", text)));
+                    }
+                }
+            },
+            None => None
+        }
+    };
+
+    let to_address = |address| match condition {
+        Some(JumpCondition::Register(condition)) => Instruction::JumpIf { condition, address },
+        Some(JumpCondition::Flags(condition)) => Instruction::JumpFlags { condition, address },
+        None => Instruction::Jump { address }
+    };
+
+    match target {
+        Target::Register(register) => match condition {
+            Some(JumpCondition::Register(condition)) => emit(Instruction::JumpRegisterIf { condition, register }, out),
+            Some(JumpCondition::Flags(condition)) => emit(Instruction::JumpRegisterFlags { condition, register }, out),
+            None => emit(Instruction::JumpRegister { register }, out)
+        },
+        Target::Address(address) => emit(to_address(address), out),
+        Target::Label(label) => emit_with_label(to_address(0), label, line, mentioned_labels, out)
+    }
+
+    Ok(())
+}
+
+fn call_instruction(line: &mut Line, mentioned_labels: &mut Vec<Relocation>, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    match line.target()? {
+        Target::Register(register) => emit(Instruction::CallRegister { register }, out),
+        Target::Address(address) => emit(Instruction::Call { address }, out),
+        Target::Label(label) => emit_with_label(Instruction::Call { address: 0 }, label, line, mentioned_labels, out)
+    }
+
+    Ok(())
+}
+
+fn vectors_instruction(line: &mut Line, mentioned_labels: &mut Vec<Relocation>, out: &mut Vec<u8>) -> Result<(), Diagnostic> {
+    match line.target()? {
+        Target::Register(register) => emit(Instruction::VectorsRegister { register }, out),
+        Target::Address(address) => emit(Instruction::Vectors { address }, out),
+        Target::Label(label) => emit_with_label(Instruction::Vectors { address: 0 }, label, line, mentioned_labels, out)
+    }
+
+    Ok(())
+}
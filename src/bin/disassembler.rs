@@ -0,0 +1,14 @@
+use c64::disassembler::disassemble;
+
+fn main() {
+    let mut args = std::env::args();
+    let bin_filename = args.nth(1).expect("no binary file given");
+
+    let bin = std::fs::read(bin_filename).unwrap();
+    let listing = disassemble(&bin);
+
+    match args.next() {
+        Some(out_filename) => std::fs::write(out_filename, listing).unwrap(),
+        None => print!("{}", listing)
+    }
+}
@@ -0,0 +1,272 @@
+use std::{borrow::Cow, cell::RefCell, ops::Range, rc::Rc};
+
+use crate::{
+    emulator::{FaultKind, RAM_SIZE},
+    ram::Ram
+};
+
+/// How many interrupt lines there are, each one has an obyte entry in the vector table
+pub const IRQ_LINES: u8 = 16;
+
+/// Something the host maps into the address space, like a console or a timer
+///
+/// offsets are relative to where the device is mapped, so a device doesn't care where it ends up
+pub trait Device {
+    /// Read the byte at `offset`, reading can have side effects like taking a key out of a queue
+    fn read(&mut self, offset: u64) -> u8;
+
+    fn write(&mut self, offset: u64, value: u8);
+
+    /// Called once after every instruction with how many cycles it took
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Called after `tick` with access to ram, for devices that copy data in and out of memory on their own
+    fn dma(&mut self, _dma: &mut Dma) {}
+
+    /// Whether the device wants the cpu's attention, it stays raised until the device lowers it,
+    /// usually when the program writes to one of its ports
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+/// Lets the host keep a handle on a device after handing it to the bus, to look at it once the program is done
+impl<D: Device> Device for Rc<RefCell<D>> {
+    fn read(&mut self, offset: u64) -> u8 {
+        self.borrow_mut().read(offset)
+    }
+
+    fn write(&mut self, offset: u64, value: u8) {
+        self.borrow_mut().write(offset, value);
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.borrow_mut().tick(cycles);
+    }
+
+    fn dma(&mut self, dma: &mut Dma) {
+        self.borrow_mut().dma(dma);
+    }
+
+    fn irq(&self) -> bool {
+        self.borrow().irq()
+    }
+}
+
+/// Ram as a device sees it during `Device::dma`, devices can't reach each other through it
+pub struct Dma<'a> {
+    ram: &'a mut Ram
+}
+
+impl<'a> Dma<'a> {
+    pub fn new(ram: &'a mut Ram) -> Dma<'a> {
+        Dma {
+            ram
+        }
+    }
+
+    /// Fill `buf` with the ram starting at `addr`
+    pub fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), FaultKind> {
+        self.ram.read(addr, buf)
+    }
+
+    /// Copy `bytes` into ram starting at `addr`
+    pub fn write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), FaultKind> {
+        self.ram.write(addr, bytes)
+    }
+}
+
+/// Everything the cpu reads from and writes to goes through this
+pub trait Bus {
+    /// Fill `buf` with the bytes starting at `addr`
+    ///
+    /// nothing is read if any of the bytes isn't mapped
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), FaultKind>;
+
+    /// Write `bytes` starting at `addr`
+    ///
+    /// nothing is written if any of the bytes isn't mapped
+    fn write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), FaultKind>;
+
+    /// Plain memory that can be looked at without side effects, `None` if any of it isn't plain memory
+    ///
+    /// borrowed when the memory is laid out in one piece, copied when it isn't
+    fn memory(&self, range: Range<u64>) -> Option<Cow<'_, [u8]>>;
+
+    /// Copy `bytes` into plain memory starting at `addr` without side effects, nothing is copied if any of it isn't plain memory
+    fn load(&mut self, addr: u64, bytes: &[u8]) -> Result<(), FaultKind>;
+
+    /// How many bytes of plain memory there are from address 0
+    fn ram_size(&self) -> u64;
+
+    /// Called once after every instruction with how many cycles it took
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// The lowest interrupt line that is raised, if any
+    fn irq(&self) -> Option<u8> {
+        None
+    }
+}
+
+/// A device and where it's mapped
+struct Mapping {
+    range: Range<u64>,
+    /// interrupt line the device raises, `None` if it isn't wired to one
+    irq: Option<u8>,
+    device: Box<dyn Device>
+}
+
+/// Ram from address 0 up to its size and devices mapped wherever the host wants above it
+pub struct SystemBus {
+    ram: Ram,
+    devices: Vec<Mapping>
+}
+
+impl SystemBus {
+    /// A bus with `RAM_SIZE` bytes of ram
+    pub fn new() -> SystemBus {
+        SystemBus::with_ram_size(RAM_SIZE as u64)
+    }
+
+    /// A bus with `size` bytes of ram, only the parts that get written to take up host memory
+    pub fn with_ram_size(size: u64) -> SystemBus {
+        SystemBus {
+            ram: Ram::new(size),
+            devices: Vec::new()
+        }
+    }
+
+    pub fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    /// Map `device` to `len` bytes starting at `base`
+    ///
+    /// panics if the range is empty or overlaps ram or another device
+    pub fn map(&mut self, base: u64, len: u64, device: Box<dyn Device>) {
+        self.map_device(base, len, None, device);
+    }
+
+    /// Map `device` like `map` and wire it to interrupt line `irq`
+    ///
+    /// panics if `irq` isn't below `IRQ_LINES` or `map` would
+    pub fn map_with_irq(&mut self, base: u64, len: u64, irq: u8, device: Box<dyn Device>) {
+        assert!(irq < IRQ_LINES, "there are only {} interrupt lines", IRQ_LINES);
+
+        self.map_device(base, len, Some(irq), device);
+    }
+
+    fn map_device(&mut self, base: u64, len: u64, irq: Option<u8>, device: Box<dyn Device>) {
+        let end = base.checked_add(len).expect("device range goes past the end of the address space");
+
+        assert!(len > 0, "can't map a device to 0 bytes");
+        assert!(base >= self.ram.size(), "device at {} overlaps ram", base);
+        assert!(
+            self.devices.iter().all(|mapping| end <= mapping.range.start || base >= mapping.range.end),
+            "device at {}..{} overlaps another device", base, end
+        );
+
+        self.devices.push(Mapping {
+            range: base..end,
+            irq,
+            device
+        });
+    }
+
+    /// Make sure every byte of `len` bytes from `addr` is ram or a device
+    fn check_mapped(&self, addr: u64, len: usize) -> Result<(), FaultKind> {
+        for i in 0..len as u64 {
+            let byte_addr = addr.checked_add(i).ok_or(FaultKind::BadAddress(u64::MAX))?;
+
+            let mapped = byte_addr < self.ram.size() || self.devices.iter().any(|mapping| mapping.range.contains(&byte_addr));
+            if !mapped {
+                return Err(FaultKind::BadAddress(byte_addr));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The device `addr` falls in and the offset into it
+    fn device(&mut self, addr: u64) -> Option<(&mut Box<dyn Device>, u64)> {
+        self.devices
+            .iter_mut()
+            .find(|mapping| mapping.range.contains(&addr))
+            .map(|mapping| (&mut mapping.device, addr - mapping.range.start))
+    }
+}
+
+impl Default for SystemBus {
+    fn default() -> SystemBus {
+        SystemBus::new()
+    }
+}
+
+impl Bus for SystemBus {
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), FaultKind> {
+        if self.ram.contains(&(addr..addr.saturating_add(buf.len() as u64))) {
+            return self.ram.read(addr, buf);
+        }
+
+        self.check_mapped(addr, buf.len())?;
+
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let byte_addr = addr + i as u64;
+
+            if byte_addr < self.ram.size() {
+                self.ram.read(byte_addr, std::slice::from_mut(byte))?;
+            } else {
+                let (device, offset) = self.device(byte_addr).unwrap();
+                *byte = device.read(offset);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), FaultKind> {
+        if self.ram.contains(&(addr..addr.saturating_add(bytes.len() as u64))) {
+            return self.ram.write(addr, bytes);
+        }
+
+        self.check_mapped(addr, bytes.len())?;
+
+        for (i, byte) in bytes.iter().enumerate() {
+            let byte_addr = addr + i as u64;
+
+            if byte_addr < self.ram.size() {
+                self.ram.write(byte_addr, &[*byte])?;
+            } else {
+                let (device, offset) = self.device(byte_addr).unwrap();
+                device.write(offset, *byte);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn memory(&self, range: Range<u64>) -> Option<Cow<'_, [u8]>> {
+        self.ram.slice(range)
+    }
+
+    fn load(&mut self, addr: u64, bytes: &[u8]) -> Result<(), FaultKind> {
+        self.ram.write(addr, bytes)
+    }
+
+    fn ram_size(&self) -> u64 {
+        self.ram.size()
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        let mut dma = Dma::new(&mut self.ram);
+
+        for mapping in &mut self.devices {
+            mapping.device.tick(cycles);
+            mapping.device.dma(&mut dma);
+        }
+    }
+
+    fn irq(&self) -> Option<u8> {
+        self.devices.iter().filter(|mapping| mapping.device.irq()).filter_map(|mapping| mapping.irq).min()
+    }
+}
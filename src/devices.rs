@@ -0,0 +1,52 @@
+pub mod console;
+pub mod disk;
+pub mod framebuffer;
+pub mod random;
+pub mod rtc;
+pub mod timer;
+pub mod uart;
+
+use std::{
+    io::{self, Read},
+    sync::mpsc::{self, Receiver}
+};
+
+/// Start of the address range the `c64` binary maps its devices to, at the top of the address space, above any ram
+pub const IO_BASE: u64 = 0xFFFF_FFFF_F000_0000;
+
+/// Byte `offset` of the big endian `value`
+pub(crate) fn get_byte(value: u64, offset: u64) -> u8 {
+    value.to_be_bytes()[offset as usize]
+}
+
+/// `value` with byte `offset` replaced, big endian
+pub(crate) fn set_byte(value: u64, offset: u64, byte: u8) -> u64 {
+    let mut bytes = value.to_be_bytes();
+    bytes[offset as usize] = byte;
+    u64::from_be_bytes(bytes)
+}
+
+/// Send every byte `open` gives back down a channel from a background thread, so the cpu never waits on it
+///
+/// `open` runs on that thread too, opening something like a named pipe can block until the other end shows up.
+/// the channel disconnects once the input ends or fails
+pub(crate) fn read_in_background<R: Read>(open: impl FnOnce() -> io::Result<R> + Send + 'static) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let input = match open() {
+            Ok(input) => input,
+            Err(_) => return
+        };
+
+        // a read only waits for the first byte, so buffering never holds back what has already arrived
+        for byte in io::BufReader::new(input).bytes() {
+            match byte {
+                Ok(byte) if sender.send(byte).is_ok() => {}
+                _ => break
+            }
+        }
+    });
+
+    receiver
+}
@@ -0,0 +1,146 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+    sync::mpsc::{Receiver, TryRecvError}
+};
+
+use crate::bus::Device;
+
+use super::{read_in_background, IO_BASE};
+
+pub const CONSOLE_BASE: u64 = IO_BASE;
+pub const CONSOLE_LEN: u64 = 2;
+
+/// Writing a byte here prints it, reading takes the next input byte or 0 if there is none
+pub const DATA_PORT: u64 = 0;
+/// Read only, a combination of the `STATUS_` bits
+pub const STATUS_PORT: u64 = 1;
+
+/// a byte is waiting to be read from `DATA_PORT`
+pub const STATUS_READY: u8 = 1;
+/// the input has ended and no more bytes will come
+pub const STATUS_CLOSED: u8 = 2;
+
+/// Bytes coming in from the host, read through a channel so the cpu never waits on them
+///
+/// clones are handles on the same bytes, that's how the console and the `READ_LINE` syscall share stdin
+/// without either one taking bytes meant for the other
+#[derive(Clone)]
+pub struct Input(Rc<RefCell<InputState>>);
+
+struct InputState {
+    receiver: Option<Receiver<u8>>,
+    /// opens `receiver` the first time anything reads, so nothing is read until the program asks for it
+    connect: Option<fn() -> Receiver<u8>>,
+    /// a byte taken out of `receiver` by a status read that hasn't been read yet
+    pending: Option<u8>
+}
+
+impl Input {
+    /// `receiver` - `None` for input that has already ended
+    pub fn new(receiver: Option<Receiver<u8>>) -> Input {
+        Input(Rc::new(RefCell::new(InputState {
+            receiver,
+            connect: None,
+            pending: None
+        })))
+    }
+
+    /// The host's stdin, read on its own thread once something first reads it
+    pub fn stdin() -> Input {
+        let input = Input::new(None);
+        input.0.borrow_mut().connect = Some(read_stdin);
+        input
+    }
+
+    /// Move the next byte into `pending` without waiting if there is one, returns whether a byte
+    /// is waiting and whether the input has ended
+    fn poll(&self) -> (bool, bool) {
+        let mut state = self.0.borrow_mut();
+
+        if state.pending.is_some() {
+            return (true, false);
+        }
+
+        if let Some(connect) = state.connect.take() {
+            state.receiver = Some(connect());
+        }
+
+        match state.receiver.as_ref().map(Receiver::try_recv) {
+            Some(Ok(byte)) => {
+                state.pending = Some(byte);
+                (true, false)
+            }
+            Some(Err(TryRecvError::Empty)) => (false, false),
+            Some(Err(TryRecvError::Disconnected)) | None => (false, true)
+        }
+    }
+
+    /// Take the waiting byte, `None` if there isn't one yet
+    pub fn try_read_byte(&self) -> Option<u8> {
+        self.poll();
+        self.0.borrow_mut().pending.take()
+    }
+
+    /// Wait for the next byte, `None` once the input has ended
+    pub fn read_byte(&self) -> Option<u8> {
+        if let Some(byte) = self.try_read_byte() {
+            return Some(byte);
+        }
+
+        let state = self.0.borrow();
+        state.receiver.as_ref()?.recv().ok()
+    }
+}
+
+/// Send every byte of stdin down a channel from a background thread
+fn read_stdin() -> Receiver<u8> {
+    read_in_background(|| Ok(io::stdin().lock()))
+}
+
+/// A console that prints to `output` and reads whatever comes in on `input`
+///
+/// a program polls `STATUS_PORT` instead of waiting for input
+pub struct Console<W: Write> {
+    output: W,
+    input: Input
+}
+
+impl<W: Write> Console<W> {
+    /// `input` - `None` for a console that never gets any input
+    pub fn new(output: W, input: Option<Receiver<u8>>) -> Console<W> {
+        Console::with_input(output, Input::new(input))
+    }
+
+    /// A console reading from `input`, which something else like the `READ_LINE` syscall can share
+    pub fn with_input(output: W, input: Input) -> Console<W> {
+        Console {
+            output,
+            input
+        }
+    }
+}
+
+impl<W: Write> Device for Console<W> {
+    fn read(&mut self, offset: u64) -> u8 {
+        // whatever was printed should be on screen before the program waits for input
+        let _ = self.output.flush();
+
+        match offset {
+            DATA_PORT => self.input.try_read_byte().unwrap_or(0),
+            STATUS_PORT => match self.input.poll() {
+                (true, _) => STATUS_READY,
+                (false, true) => STATUS_CLOSED,
+                (false, false) => 0
+            },
+            _ => {0}
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u8) {
+        if offset == DATA_PORT {
+            let _ = self.output.write_all(&[value]);
+        }
+    }
+}
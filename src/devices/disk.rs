@@ -0,0 +1,153 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path
+};
+
+use crate::bus::{Device, Dma};
+
+use super::{get_byte, set_byte, IO_BASE};
+
+pub const DISK_BASE: u64 = IO_BASE + 0x40;
+pub const DISK_LEN: u64 = 0x20;
+
+/// How many bytes a command moves
+pub const SECTOR_SIZE: usize = 512;
+
+/// obyte, the sector the next command works on
+pub const SECTOR_PORT: u64 = 0x00;
+/// obyte, the ram address the sector is copied to or from
+pub const BUFFER_PORT: u64 = 0x08;
+/// obyte, read only, how many sectors the image holds
+pub const SECTORS_PORT: u64 = 0x10;
+/// byte, writing one of the `COMMAND_` values starts it, reads as 0
+pub const COMMAND_PORT: u64 = 0x18;
+/// byte, read only, a combination of the `STATUS_` bits
+pub const STATUS_PORT: u64 = 0x19;
+
+/// copy the sector into ram at the buffer address
+pub const COMMAND_READ: u8 = 1;
+/// copy a sector's worth of ram from the buffer address into the sector
+pub const COMMAND_WRITE: u8 = 2;
+
+/// a command was started and hasn't finished yet
+pub const STATUS_BUSY: u8 = 1;
+/// the last command failed, the sector or buffer was out of range or the host couldn't do it
+pub const STATUS_ERROR: u8 = 2;
+
+/// A disk made of `SECTOR_SIZE` byte sectors, kept in a host file so data outlives the program
+///
+/// a command copies a whole sector between the image and ram without the cpu's help, it finishes
+/// at the end of the instruction that started it, so a program polls `STATUS_PORT` until it's no longer busy.
+/// writing past the last sector grows the image
+pub struct Disk<F: Read + Write + Seek> {
+    image: F,
+    sectors: u64,
+    sector: u64,
+    buffer: u64,
+    /// command waiting for the next `dma`
+    command: Option<u8>,
+    status: u8
+}
+
+impl Disk<File> {
+    /// Use the file at `path` as the image, it's created empty if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Disk<File>> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        Disk::new(file)
+    }
+}
+
+impl<F: Read + Write + Seek> Disk<F> {
+    /// a last sector that's only partly there reads as if the rest were zeros
+    pub fn new(mut image: F) -> io::Result<Disk<F>> {
+        let len = image.seek(SeekFrom::End(0))?;
+
+        Ok(Disk {
+            image,
+            sectors: len.div_ceil(SECTOR_SIZE as u64),
+            sector: 0,
+            buffer: 0,
+            command: None,
+            status: 0
+        })
+    }
+
+    pub fn image(&self) -> &F {
+        &self.image
+    }
+
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    fn seek_to(&mut self, sector: u64) -> Option<()> {
+        let position = sector.checked_mul(SECTOR_SIZE as u64)?;
+        self.image.seek(SeekFrom::Start(position)).ok()?;
+        Some(())
+    }
+
+    fn read_sector(&mut self, dma: &mut Dma) -> Option<()> {
+        if self.sector >= self.sectors {
+            return None;
+        }
+
+        self.seek_to(self.sector)?;
+
+        let mut data = Vec::with_capacity(SECTOR_SIZE);
+        (&mut self.image).take(SECTOR_SIZE as u64).read_to_end(&mut data).ok()?;
+        data.resize(SECTOR_SIZE, 0);
+
+        dma.write(self.buffer, &data).ok()
+    }
+
+    fn write_sector(&mut self, dma: &mut Dma) -> Option<()> {
+        let mut data = [0; SECTOR_SIZE];
+        dma.read(self.buffer, &mut data).ok()?;
+
+        self.seek_to(self.sector)?;
+        self.image.write_all(&data).ok()?;
+        self.image.flush().ok()?;
+
+        self.sectors = self.sectors.max(self.sector + 1);
+        Some(())
+    }
+}
+
+impl<F: Read + Write + Seek> Device for Disk<F> {
+    fn read(&mut self, offset: u64) -> u8 {
+        match offset {
+            SECTOR_PORT..=0x07 => get_byte(self.sector, offset - SECTOR_PORT),
+            BUFFER_PORT..=0x0f => get_byte(self.buffer, offset - BUFFER_PORT),
+            SECTORS_PORT..=0x17 => get_byte(self.sectors, offset - SECTORS_PORT),
+            STATUS_PORT => self.status,
+            _ => {0}
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u8) {
+        match offset {
+            SECTOR_PORT..=0x07 => self.sector = set_byte(self.sector, offset - SECTOR_PORT, value),
+            BUFFER_PORT..=0x0f => self.buffer = set_byte(self.buffer, offset - BUFFER_PORT, value),
+            COMMAND_PORT => {
+                self.command = Some(value);
+                self.status = STATUS_BUSY;
+            }
+            _ => {}
+        }
+    }
+
+    fn dma(&mut self, dma: &mut Dma) {
+        let done = match self.command.take() {
+            Some(COMMAND_READ) => self.read_sector(dma),
+            Some(COMMAND_WRITE) => self.write_sector(dma),
+            Some(_) => None,
+            None => return
+        };
+
+        self.status = match done {
+            Some(()) => 0,
+            None => STATUS_ERROR
+        };
+    }
+}
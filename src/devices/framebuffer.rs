@@ -0,0 +1,443 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf}
+};
+
+use crate::bus::Device;
+
+use super::IO_BASE;
+
+pub const FRAMEBUFFER_BASE: u64 = IO_BASE + 0x1_0000;
+pub const FRAMEBUFFER_LEN: u64 = 0x2_0000;
+
+/// Size of the picture in pixels, the same in both modes
+pub const SCREEN_WIDTH: usize = 320;
+pub const SCREEN_HEIGHT: usize = 200;
+
+/// Size of the text grid in characters, each character is 8x8 pixels
+pub const TEXT_COLUMNS: usize = SCREEN_WIDTH / 8;
+pub const TEXT_ROWS: usize = SCREEN_HEIGHT / 8;
+
+/// byte, one of the `MODE_` values
+pub const MODE_PORT: u64 = 0x0000;
+/// byte, writing anything here saves a snapshot if the host gave the framebuffer somewhere to save them
+pub const SNAPSHOT_PORT: u64 = 0x0001;
+/// 16 colors of 3 bytes each, red green blue
+pub const PALETTE_PORT: u64 = 0x0100;
+/// one ascii character per cell, row by row
+pub const TEXT_PORT: u64 = 0x1000;
+/// one byte per cell, background color index in the high nibble and foreground in the low one
+pub const COLOR_PORT: u64 = 0x1400;
+/// one byte per pixel, row by row, only the low nibble is used as the color index
+pub const BITMAP_PORT: u64 = 0x1_0000;
+
+/// characters drawn from `TEXT_PORT` in the colors from `COLOR_PORT`
+pub const MODE_TEXT: u8 = 0;
+/// pixels drawn from `BITMAP_PORT`
+pub const MODE_BITMAP: u8 = 1;
+
+/// What every cell starts as, light blue on blue
+pub const DEFAULT_COLOR: u8 = 0x6E;
+
+/// The palette the framebuffer starts with
+pub const DEFAULT_PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], // black
+    [0xFF, 0xFF, 0xFF], // white
+    [0x68, 0x37, 0x2B], // red
+    [0x70, 0xA4, 0xB2], // cyan
+    [0x6F, 0x3D, 0x86], // purple
+    [0x58, 0x8D, 0x43], // green
+    [0x35, 0x28, 0x79], // blue
+    [0xB8, 0xC7, 0x6F], // yellow
+    [0x6F, 0x4F, 0x25], // orange
+    [0x43, 0x39, 0x00], // brown
+    [0x9A, 0x67, 0x59], // light red
+    [0x44, 0x44, 0x44], // dark grey
+    [0x6C, 0x6C, 0x6C], // grey
+    [0x9A, 0xD2, 0x84], // light green
+    [0x6C, 0x5E, 0xB5], // light blue
+    [0x95, 0x95, 0x95]  // light grey
+];
+
+/// A screen the program draws on by writing to memory, nothing is shown while it runs
+///
+/// the host looks at it with `render`, and the program can ask for a snapshot through `SNAPSHOT_PORT`
+pub struct Framebuffer {
+    memory: Vec<u8>,
+    /// where snapshots go, `frame.png` saves `frame-0.png`, `frame-1.png` and so on
+    snapshot_path: Option<PathBuf>,
+    snapshots: u64,
+    /// snapshots that couldn't be saved and why, for the host to report
+    snapshot_errors: Vec<(PathBuf, io::Error)>
+}
+
+impl Framebuffer {
+    pub fn new() -> Framebuffer {
+        let mut memory = vec![0; FRAMEBUFFER_LEN as usize];
+
+        for (i, color) in DEFAULT_PALETTE.iter().enumerate() {
+            let start = PALETTE_PORT as usize + i * 3;
+            memory[start..start + 3].copy_from_slice(color);
+        }
+
+        let colors = COLOR_PORT as usize;
+        memory[colors..colors + TEXT_COLUMNS * TEXT_ROWS].fill(DEFAULT_COLOR);
+
+        Framebuffer {
+            memory,
+            snapshot_path: None,
+            snapshots: 0,
+            snapshot_errors: Vec::new()
+        }
+    }
+
+    /// Save snapshots the program asks for next to `path`, numbered from 0
+    ///
+    /// the format comes from the extension, see `Image::save`
+    pub fn with_snapshots(path: impl Into<PathBuf>) -> Framebuffer {
+        Framebuffer {
+            snapshot_path: Some(path.into()),
+            ..Framebuffer::new()
+        }
+    }
+
+    /// How many snapshots the program has asked for so far, including ones that couldn't be saved
+    pub fn snapshots(&self) -> u64 {
+        self.snapshots
+    }
+
+    /// The snapshots that couldn't be saved, with the path each was going to and what went wrong
+    pub fn snapshot_errors(&self) -> &[(PathBuf, io::Error)] {
+        &self.snapshot_errors
+    }
+
+    fn color(&self, index: u8) -> [u8; 3] {
+        let start = PALETTE_PORT as usize + (index & 0x0F) as usize * 3;
+        [self.memory[start], self.memory[start + 1], self.memory[start + 2]]
+    }
+
+    /// Draw what's on the screen right now
+    pub fn render(&self) -> Image {
+        let mut image = Image::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+
+        match self.memory[MODE_PORT as usize] {
+            MODE_BITMAP => {
+                for y in 0..SCREEN_HEIGHT {
+                    for x in 0..SCREEN_WIDTH {
+                        let index = self.memory[BITMAP_PORT as usize + y * SCREEN_WIDTH + x];
+                        image.set_pixel(x, y, self.color(index));
+                    }
+                }
+            }
+            // anything that isn't a known mode shows text, so a stray write doesn't blank the screen
+            _ => {
+                for row in 0..TEXT_ROWS {
+                    for column in 0..TEXT_COLUMNS {
+                        let cell = row * TEXT_COLUMNS + column;
+                        let glyph = glyph(self.memory[TEXT_PORT as usize + cell]);
+                        let colors = self.memory[COLOR_PORT as usize + cell];
+                        let foreground = self.color(colors & 0x0F);
+                        let background = self.color(colors >> 4);
+
+                        for (y, bits) in glyph.iter().enumerate() {
+                            for x in 0..8 {
+                                let color = if bits & (1 << x) != 0 { foreground } else { background };
+                                image.set_pixel(column * 8 + x, row * 8 + y, color);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    fn snapshot(&mut self) {
+        let path = match &self.snapshot_path {
+            Some(path) => numbered_path(path, self.snapshots),
+            None => return
+        };
+
+        // a device has no way to fault the cpu, and a missed snapshot shouldn't stop the program anyway,
+        // so the error is kept for the host
+        if let Err(error) = self.render().save(&path) {
+            self.snapshot_errors.push((path, error));
+        }
+
+        self.snapshots += 1;
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer::new()
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: u64) -> u8 {
+        match offset {
+            SNAPSHOT_PORT => {0}
+            _ => {self.memory[offset as usize]}
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u8) {
+        match offset {
+            SNAPSHOT_PORT => self.snapshot(),
+            _ => self.memory[offset as usize] = value
+        }
+    }
+}
+
+/// `path` with `-<number>` put before the extension
+fn numbered_path(path: &Path, number: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, number)
+    };
+
+    path.with_file_name(name)
+}
+
+/// The 8x8 picture of `character`, anything outside printable ascii is blank
+fn glyph(character: u8) -> [u8; 8] {
+    match character {
+        0x20..=0x7E => FONT[(character - 0x20) as usize],
+        _ => [0; 8]
+    }
+}
+
+/// A picture made of rgb pixels, row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// 3 bytes per pixel, red green blue
+    pub pixels: Vec<u8>
+}
+
+impl Image {
+    /// A black image
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height * 3]
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let start = (y * self.width + x) * 3;
+        [self.pixels[start], self.pixels[start + 1], self.pixels[start + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        let start = (y * self.width + x) * 3;
+        self.pixels[start..start + 3].copy_from_slice(&color);
+    }
+
+    /// Binary ppm (P6), about the simplest image format there is
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        ppm.extend_from_slice(&self.pixels);
+        ppm
+    }
+
+    /// Png with uncompressed deflate blocks, bigger than it needs to be but anything can open it
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bits per channel, rgb, deflate, no filtering, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        png_chunk(&mut png, b"IHDR", &header);
+
+        // every row starts with its filter type, 0 means none
+        let mut raw = Vec::with_capacity(self.height * (self.width * 3 + 1));
+        for row in self.pixels.chunks(self.width * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// Write the image to `path` as png if it ends in `.png` and as ppm otherwise
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let is_png = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+
+        let bytes = if is_png { self.to_png() } else { self.to_ppm() };
+
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(&bytes)
+    }
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// `data` wrapped in a zlib stream without compressing it
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate, 32k window, no dictionary, lowest compression level
+    let mut zlib = vec![0x78, 0x01];
+
+    // stored blocks hold at most 65535 bytes, and there has to be at least one even with no data
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+/// Printable ascii from space to `~`, one byte per row with the leftmost pixel in the lowest bit
+const FONT: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], // !
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], // #
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], // $
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], // %
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], // &
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], // (
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], // )
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], // *
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ,
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // .
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], // /
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], // 0
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], // 1
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], // 2
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], // 3
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], // 4
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], // 5
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], // 6
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], // 7
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], // 8
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], // 9
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], // :
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ;
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], // <
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], // =
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], // >
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], // ?
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], // @
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], // A
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], // B
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], // C
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], // D
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], // E
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], // F
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], // G
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], // H
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // I
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], // J
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], // K
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], // L
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], // M
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], // N
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], // O
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], // P
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], // Q
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], // R
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], // S
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // T
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], // U
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // V
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], // W
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], // X
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], // Y
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], // Z
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], // [
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], // backslash
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], // ]
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // _
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], // a
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], // b
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], // c
+    [0x38, 0x30, 0x30, 0x3E, 0x33, 0x33, 0x6E, 0x00], // d
+    [0x00, 0x00, 0x1E, 0x33, 0x3F, 0x03, 0x1E, 0x00], // e
+    [0x1C, 0x36, 0x06, 0x0F, 0x06, 0x06, 0x0F, 0x00], // f
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], // g
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], // h
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // i
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], // j
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], // k
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // l
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], // m
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], // n
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], // o
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], // p
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], // q
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], // r
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], // s
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], // t
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], // u
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // v
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], // w
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], // x
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], // y
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], // z
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], // {
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // |
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], // }
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]  // ~
+];
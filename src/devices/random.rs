@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bus::Device;
+
+use super::{get_byte, set_byte, IO_BASE};
+
+pub const RANDOM_BASE: u64 = IO_BASE + 0x90;
+pub const RANDOM_LEN: u64 = 0x10;
+
+/// obyte, read only, reading its first byte makes a new number that every other byte is read from
+pub const VALUE_PORT: u64 = 0x00;
+/// obyte, writing it starts the sequence over from the new seed, reads back the seed
+pub const SEED_PORT: u64 = 0x08;
+
+/// A pseudo random number generator, the same seed always gives the same numbers
+///
+/// uses splitmix64, which is fast, small and fine with any seed including 0, but no good for cryptography
+#[derive(Debug)]
+pub struct Random {
+    seed: u64,
+    state: u64,
+    value: u64
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random {
+            seed,
+            state: seed,
+            value: 0
+        }
+    }
+
+    /// Seeded from the host's clock, so every run is different
+    pub fn from_time() -> Random {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Random::new(since_epoch.as_nanos() as u64)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The next number in the sequence
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl Device for Random {
+    fn read(&mut self, offset: u64) -> u8 {
+        if offset == VALUE_PORT {
+            self.value = self.next_u64();
+        }
+
+        match offset {
+            VALUE_PORT..=0x07 => get_byte(self.value, offset - VALUE_PORT),
+            SEED_PORT..=0x0f => get_byte(self.seed, offset - SEED_PORT),
+            _ => {0}
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u8) {
+        if let SEED_PORT..=0x0f = offset {
+            *self = Random::new(set_byte(self.seed, offset - SEED_PORT, value));
+        }
+    }
+}
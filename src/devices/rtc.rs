@@ -0,0 +1,79 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::bus::Device;
+
+use super::{get_byte, IO_BASE};
+
+pub const RTC_BASE: u64 = IO_BASE + 0x80;
+pub const RTC_LEN: u64 = 0x10;
+
+/// obyte, read only, whole seconds since the unix epoch, reading its first byte takes the time
+/// that every other byte is read from
+pub const SECONDS_PORT: u64 = 0x00;
+/// obyte, read only, nanoseconds into the second, from the same time as `SECONDS_PORT`
+pub const NANOS_PORT: u64 = 0x08;
+
+/// A real time clock
+///
+/// the time is only taken when the program reads the first byte of `SECONDS_PORT`, so `read obyte` on
+/// both ports gives seconds and nanoseconds that belong together
+#[derive(Debug, Default)]
+pub struct Rtc {
+    /// always report this instead of the host's clock
+    frozen: Option<Duration>,
+    /// nanoseconds added to the host's clock
+    offset: i128,
+    latched: Duration
+}
+
+impl Rtc {
+    /// A clock that follows the host's
+    pub fn new() -> Rtc {
+        Rtc::default()
+    }
+
+    /// A clock stuck at `since_epoch`, for runs that have to come out the same every time
+    pub fn frozen(since_epoch: Duration) -> Rtc {
+        Rtc {
+            frozen: Some(since_epoch),
+            ..Rtc::default()
+        }
+    }
+
+    /// A clock that follows the host's but is `seconds` ahead of it, or behind if it's negative
+    pub fn with_offset(seconds: i64) -> Rtc {
+        Rtc {
+            offset: seconds as i128 * 1_000_000_000,
+            ..Rtc::default()
+        }
+    }
+
+    /// The time the clock shows right now
+    pub fn now(&self) -> Duration {
+        if let Some(frozen) = self.frozen {
+            return frozen;
+        }
+
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        // a clock set before 1970 shows 1970
+        let nanos = (since_epoch.as_nanos() as i128 + self.offset).max(0);
+
+        Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32)
+    }
+}
+
+impl Device for Rtc {
+    fn read(&mut self, offset: u64) -> u8 {
+        if offset == SECONDS_PORT {
+            self.latched = self.now();
+        }
+
+        match offset {
+            SECONDS_PORT..=0x07 => get_byte(self.latched.as_secs(), offset - SECONDS_PORT),
+            NANOS_PORT..=0x0f => get_byte(self.latched.subsec_nanos() as u64, offset - NANOS_PORT),
+            _ => {0}
+        }
+    }
+
+    fn write(&mut self, _offset: u64, _value: u8) {}
+}
@@ -0,0 +1,92 @@
+use crate::bus::Device;
+
+use super::{get_byte, set_byte, IO_BASE};
+
+pub const TIMER_BASE: u64 = IO_BASE + 0x10;
+pub const TIMER_LEN: u64 = 0x20;
+/// Interrupt line the `c64` binary wires the timer to
+pub const TIMER_IRQ: u8 = 0;
+
+/// obyte, cycles left until the timer goes off, counts down while the timer is enabled
+pub const COUNT_PORT: u64 = 0x00;
+/// obyte, what `COUNT_PORT` starts over from once the timer goes off, 0 stops the timer instead
+pub const RELOAD_PORT: u64 = 0x08;
+/// byte, a combination of the `CONTROL_` bits
+pub const CONTROL_PORT: u64 = 0x10;
+/// byte, `STATUS_EXPIRED` is set when the timer goes off, writing anything here clears it
+pub const STATUS_PORT: u64 = 0x11;
+/// obyte, read only, cycles gone by since the timer was created whether it's enabled or not
+pub const CYCLES_PORT: u64 = 0x18;
+
+/// count down while this is set
+pub const CONTROL_ENABLE: u8 = 1;
+/// raise the interrupt line while `STATUS_EXPIRED` is set
+pub const CONTROL_IRQ: u8 = 2;
+
+pub const STATUS_EXPIRED: u8 = 1;
+
+/// A countdown timer driven by the cpu's cycle counter
+///
+/// the obyte ports are big endian like everything else, so `write obyte a <port>` sets one in one go
+#[derive(Debug, Default)]
+pub struct Timer {
+    count: u64,
+    reload: u64,
+    control: u8,
+    status: u8,
+    cycles: u64
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer::default()
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset: u64) -> u8 {
+        match offset {
+            COUNT_PORT..=0x07 => get_byte(self.count, offset - COUNT_PORT),
+            RELOAD_PORT..=0x0f => get_byte(self.reload, offset - RELOAD_PORT),
+            CONTROL_PORT => self.control,
+            STATUS_PORT => self.status,
+            CYCLES_PORT..=0x1f => get_byte(self.cycles, offset - CYCLES_PORT),
+            _ => {0}
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u8) {
+        match offset {
+            COUNT_PORT..=0x07 => self.count = set_byte(self.count, offset - COUNT_PORT, value),
+            RELOAD_PORT..=0x0f => self.reload = set_byte(self.reload, offset - RELOAD_PORT, value),
+            CONTROL_PORT => self.control = value,
+            STATUS_PORT => self.status = 0,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+
+        if self.control & CONTROL_ENABLE == 0 || self.count == 0 {
+            return;
+        }
+
+        if cycles < self.count {
+            self.count -= cycles;
+            return;
+        }
+
+        self.status |= STATUS_EXPIRED;
+
+        // a reload shorter than an instruction only goes off once per instruction
+        self.count = match self.reload {
+            0 => 0,
+            reload => reload - (cycles - self.count) % reload
+        };
+    }
+
+    fn irq(&self) -> bool {
+        self.control & CONTROL_IRQ != 0 && self.status & STATUS_EXPIRED != 0
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    io::Write,
+    sync::mpsc::{Receiver, TryRecvError}
+};
+
+#[cfg(unix)]
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf}
+};
+
+use crate::bus::Device;
+
+#[cfg(unix)]
+use super::read_in_background;
+use super::IO_BASE;
+
+pub const UART_BASE: u64 = IO_BASE + 0x60;
+pub const UART_LEN: u64 = 4;
+/// Interrupt line the `c64` binary wires the uart to
+pub const UART_IRQ: u8 = 1;
+
+/// Writing a byte sends it, reading takes the next received byte or 0 if there is none
+pub const DATA_PORT: u64 = 0;
+/// Read only, a combination of the `STATUS_` bits
+pub const STATUS_PORT: u64 = 1;
+/// A combination of the `CONTROL_` bits
+pub const CONTROL_PORT: u64 = 2;
+
+/// a received byte is waiting to be read from `DATA_PORT`
+pub const STATUS_READY: u8 = 1;
+/// the other end has gone away and no more bytes will come
+pub const STATUS_CLOSED: u8 = 2;
+
+/// raise the interrupt line while a received byte is waiting
+pub const CONTROL_IRQ: u8 = 1;
+
+/// A serial port whose bytes go to and come from something on the host, like a socket the test harness holds
+///
+/// works like the console, a program polls `STATUS_PORT` or asks for an interrupt instead of waiting on the line
+pub struct Uart {
+    output: Box<dyn Write>,
+    input: Receiver<u8>,
+    /// a received byte taken out of `input` that hasn't been read yet
+    pending: Option<u8>,
+    closed: bool,
+    control: u8,
+    /// the pty's other end, held open so the line stays up until a client connects
+    #[cfg(unix)]
+    _pty: Option<File>
+}
+
+impl Uart {
+    /// `output` - where sent bytes go, it's flushed after every one
+    pub fn new(output: impl Write + 'static, input: Receiver<u8>) -> Uart {
+        Uart {
+            output: Box::new(output),
+            input,
+            pending: None,
+            closed: false,
+            control: 0,
+            #[cfg(unix)]
+            _pty: None
+        }
+    }
+
+    /// Connect to the unix domain socket at `path`, something on the host has to be listening there already
+    #[cfg(unix)]
+    pub fn unix_socket(path: impl AsRef<Path>) -> io::Result<Uart> {
+        let stream = UnixStream::connect(path)?;
+        let reader = stream.try_clone()?;
+
+        Ok(Uart::new(stream, read_in_background(move || Ok(reader))))
+    }
+
+    /// Receive from the named pipe at `input` and send to the one at `output`
+    ///
+    /// opening `output` waits until something opens it for reading, `input` is opened in the background
+    #[cfg(unix)]
+    pub fn pipes(input: impl Into<PathBuf>, output: impl AsRef<Path>) -> io::Result<Uart> {
+        let input = input.into();
+        let receiver = read_in_background(move || File::open(input));
+        let output = OpenOptions::new().write(true).open(output)?;
+
+        Ok(Uart::new(output, receiver))
+    }
+
+    /// Open a new pseudo terminal in raw mode, returns the uart and the path a client opens to talk to it
+    #[cfg(unix)]
+    pub fn pty() -> io::Result<(Uart, PathBuf)> {
+        let (master, slave, path) = pty::open()?;
+        let reader = master.try_clone()?;
+
+        let uart = Uart {
+            _pty: Some(slave),
+            ..Uart::new(master, read_in_background(move || Ok(reader)))
+        };
+
+        Ok((uart, path))
+    }
+
+    /// Move the next received byte into `pending` if there is one
+    fn poll(&mut self) {
+        if self.pending.is_some() || self.closed {
+            return;
+        }
+
+        match self.input.try_recv() {
+            Ok(byte) => self.pending = Some(byte),
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.closed = true
+        }
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: u64) -> u8 {
+        self.poll();
+
+        match offset {
+            DATA_PORT => self.pending.take().unwrap_or(0),
+            STATUS_PORT => match (self.pending, self.closed) {
+                (Some(_), _) => STATUS_READY,
+                (None, true) => STATUS_CLOSED,
+                (None, false) => 0
+            },
+            CONTROL_PORT => self.control,
+            _ => {0}
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u8) {
+        match offset {
+            // nothing the program can do about a line that's gone, so the byte is dropped
+            DATA_PORT => {
+                let _ = self.output.write_all(&[value]).and_then(|_| self.output.flush());
+            }
+            CONTROL_PORT => self.control = value,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, _cycles: u64) {
+        self.poll();
+    }
+
+    fn irq(&self) -> bool {
+        self.control & CONTROL_IRQ != 0 && self.pending.is_some()
+    }
+}
+
+/// std has no pseudo terminals, so these come straight from libc
+#[cfg(unix)]
+mod pty {
+    use std::{
+        ffi::{CStr, OsStr},
+        fs::{File, OpenOptions},
+        io,
+        os::{
+            fd::{AsRawFd, FromRawFd},
+            raw::{c_char, c_int},
+            unix::{ffi::OsStrExt, fs::OpenOptionsExt}
+        },
+        path::PathBuf
+    };
+
+    /// Room for any platform's `struct termios`, only libc looks inside it
+    #[repr(C, align(8))]
+    struct Termios([u8; 256]);
+
+    const O_RDWR: c_int = 2;
+    /// so the pty never becomes the emulator's controlling terminal
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const O_NOCTTY: c_int = 0o400;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const O_NOCTTY: c_int = 0x20000;
+    /// the bsds
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+    const O_NOCTTY: c_int = 0x8000;
+    const TCSANOW: c_int = 0;
+
+    extern "C" {
+        fn posix_openpt(flags: c_int) -> c_int;
+        fn grantpt(fd: c_int) -> c_int;
+        fn unlockpt(fd: c_int) -> c_int;
+        fn ptsname(fd: c_int) -> *mut c_char;
+        fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+        fn cfmakeraw(termios: *mut Termios);
+    }
+
+    /// The master end, the slave end and the slave's path
+    pub fn open() -> io::Result<(File, File, PathBuf)> {
+        // SAFETY: the fd is checked before it's owned by a File, and ptsname's string is copied out before
+        // anything else could call it
+        let (master, path) = unsafe {
+            let fd = posix_openpt(O_RDWR | O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+
+            if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let name = ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            (master, PathBuf::from(OsStr::from_bytes(CStr::from_ptr(name).to_bytes())))
+        };
+
+        let slave = OpenOptions::new().read(true).write(true).custom_flags(O_NOCTTY).open(&path)?;
+
+        // raw mode, so bytes go through as they are instead of being echoed and turned into lines
+        let mut termios = Termios([0; 256]);
+        // SAFETY: termios is bigger than libc's struct and lives for both calls
+        unsafe {
+            if tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            cfmakeraw(&mut termios);
+            if tcsetattr(slave.as_raw_fd(), TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok((master, slave, path))
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+
+use crate::isa::{decode, Instruction};
+
+/// A line of the listing, either an instruction or a byte that didn't decode
+enum Line {
+    Instruction(Instruction),
+    Data(u8)
+}
+
+/// Turn a whole binary back into assembly
+///
+/// every jump target that lands on an instruction gets a `:label_<address>` and every line
+/// is annotated with its address and raw bytes, the output can be fed back to the assembler
+pub fn disassemble(bin: &[u8]) -> String {
+    // first pass, decode everything and find where jumps go
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < bin.len() {
+        match decode(&bin[offset..]) {
+            Ok((instruction, len)) => {
+                lines.push((offset, len, Line::Instruction(instruction)));
+                offset += len;
+            }
+            Err(_) => {
+                lines.push((offset, 1, Line::Data(bin[offset])));
+                offset += 1;
+            }
+        }
+    }
+
+    let mut labels = BTreeMap::new();
+    for (_, _, line) in &lines {
+        if let Line::Instruction(instruction) = line {
+            if let Some(target) = instruction.jump_target() {
+                labels.insert(target, format!("label_{}", target));
+            }
+        }
+    }
+
+    // only addresses where an instruction starts can carry a label
+    labels.retain(|target, _| lines.iter().any(|(offset, _, _)| *offset as u64 == *target));
+
+    // second pass, print it
+    let mut out = String::new();
+
+    for (offset, len, line) in &lines {
+        if let Some(label) = labels.get(&(*offset as u64)) {
+            out += &format!(":{}\n", label);
+        }
+
+        let mut text = String::new();
+        match line {
+            Line::Instruction(instruction) => {
+                let _ = instruction.fmt_with_labels(&mut text, &|address| labels.get(&address).cloned());
+            }
+            Line::Data(byte) => {
+                text = format!("byte {}", byte);
+            }
+        }
+
+        let bytes: Vec<String> = bin[*offset..*offset + *len].iter().map(|byte| format!("{:02x}", byte)).collect();
+        out += &format!("    {:<32} ; {:>6}: {}\n", text, offset, bytes.join(" "));
+    }
+
+    out
+}
@@ -1,425 +1,866 @@
+use std::{borrow::Cow, collections::HashMap, fmt, ops::Range};
+
+use crate::{
+    bus::{Bus, SystemBus, IRQ_LINES},
+    isa::{decode, AluOp, DecodeError, FlagCondition, Instruction, InstructionDef, MAX_INSTRUCTION_LEN},
+    protection::{Access, Permissions, Protection},
+    trace::{changed_registers, MemoryWrite, TraceEvent, TraceSink}
+};
+
+/// How much ram `Emulator::new` and `SystemBus::new` give a program
 pub const RAM_SIZE: usize = 320_000;
 pub const COUNTER_REG: usize = 14;
 pub const STACK_REG: usize = 15;
 
+/// Cycles it takes to push pc and the flags and jump through the vector table
+pub const INTERRUPT_CYCLES: u64 = 8;
+
 // BYTE = 8 bits
 // DBYTE = Double byte = 16 bits
 // QBYTE = Quad byte = 32 bits
 // OBYTE = Octal byte = 64 bits
 
-pub struct Emulator {
+/// How long `Emulator::run` is allowed to go before handing control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    /// run until the program halts or faults
+    Unlimited,
+    /// execute at most this many instructions
+    Steps(u64),
+    /// stop once this many cycles have gone by, the instruction that crosses the limit still finishes
+    Cycles(u64)
+}
+
+/// Why `Emulator::run` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// the program executed `halt`
+    Halted,
+    /// a `Budget::Steps` ran out before the program halted
+    StepLimit,
+    /// a `Budget::Cycles` ran out before the program halted
+    CycleLimit,
+    /// the program did something the cpu can't carry out
+    Fault(Fault)
+}
+
+/// What went wrong when the cpu faulted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// an access touched memory outside of ram, holds the first address that was out of range
+    BadAddress(u64),
+    /// the opcode isn't part of the instruction set
+    BadOpcode,
+    /// a `<type>` byte that isn't byte/dbyte/qbyte/obyte
+    BadOperandType(u8),
+    /// a register byte that doesn't name one of the 16 registers
+    BadRegister(u8),
+    /// a condition byte that isn't 0 (false) or 1 (true)
+    BadCondition(u8),
+    DivideByZero,
+    /// a push would go past the stack's limit or over bytes loaded with `load_at`
+    StackOverflow,
+    /// a pop would read past the stack's base
+    StackUnderflow,
+    /// no handler is set for this syscall number
+    BadSyscall(u8),
+    /// the permissions set with `Emulator::protect` don't allow `access` at `address`
+    Protection {
+        address: u64,
+        access: Access
+    }
+}
+
+/// A fault raised by `Emulator::step`
+///
+/// `pc` - address of the instruction that faulted, the program counter is left pointing at it
+///
+/// `opcode` - the opcode at `pc`, `None` if the opcode itself couldn't be fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub pc: u64,
+    pub opcode: Option<u8>,
+    pub kind: FaultKind
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultKind::BadAddress(address) => write!(f, "bad address {}", address),
+            FaultKind::BadOpcode => write!(f, "bad opcode"),
+            FaultKind::BadOperandType(specified_type) => write!(f, "bad operand type {}", specified_type),
+            FaultKind::BadRegister(register) => write!(f, "bad register {}", register),
+            FaultKind::BadCondition(condition) => write!(f, "bad condition {}", condition),
+            FaultKind::DivideByZero => write!(f, "divide by zero"),
+            FaultKind::StackOverflow => write!(f, "stack overflow"),
+            FaultKind::StackUnderflow => write!(f, "stack underflow"),
+            FaultKind::BadSyscall(number) => write!(f, "no handler for syscall {}", number),
+            FaultKind::Protection { address, access } => write!(f, "{} denied for address {}", access, address)
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.opcode {
+            Some(opcode) => write!(f, "{} at address {} (opcode {})", self.kind, self.pc, opcode),
+            None => write!(f, "{} at address {}", self.kind, self.pc)
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+impl FaultKind {
+    /// The fault for an instruction that couldn't be decoded
+    ///
+    /// `fetched_end` - address right after the last byte that could be fetched, where a cut off instruction ran out of memory
+    fn from_decode(error: DecodeError, fetched_end: u64) -> FaultKind {
+        match error {
+            DecodeError::Truncated => FaultKind::BadAddress(fetched_end),
+            DecodeError::BadOpcode(_) => FaultKind::BadOpcode,
+            DecodeError::BadOperandType(specified_type) => FaultKind::BadOperandType(specified_type),
+            DecodeError::BadRegister(register) => FaultKind::BadRegister(register),
+            DecodeError::BadCondition(condition) => FaultKind::BadCondition(condition)
+        }
+    }
+}
+
+/// Status flags, set by every ALU instruction from its result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags {
+    /// the result is 0
+    pub zero: bool,
+    /// an unsigned add or mul didn't fit, or a sub had to borrow
+    pub carry: bool,
+    /// the top bit of the result is set
+    pub negative: bool,
+    /// the result doesn't fit as a signed number
+    pub overflow: bool
+}
+
+impl Flags {
+    /// The flags packed into the byte an interrupt pushes, zero is bit 0 then carry, negative and overflow
+    pub fn to_byte(self) -> u8 {
+        self.zero as u8 | (self.carry as u8) << 1 | (self.negative as u8) << 2 | (self.overflow as u8) << 3
+    }
+
+    pub fn from_byte(byte: u8) -> Flags {
+        Flags {
+            zero: byte & 1 != 0,
+            carry: byte & 2 != 0,
+            negative: byte & 4 != 0,
+            overflow: byte & 8 != 0
+        }
+    }
+
+    /// Flags with only `zero` and `negative` taken from `result`
+    fn from_result(result: u64) -> Flags {
+        Flags {
+            zero: result == 0,
+            carry: false,
+            negative: (result as i64) < 0,
+            overflow: false
+        }
+    }
+
+    pub fn test(self, condition: FlagCondition) -> bool {
+        match condition {
+            FlagCondition::Zero => self.zero,
+            FlagCondition::NotZero => !self.zero,
+            FlagCondition::Carry => self.carry,
+            FlagCondition::NotCarry => !self.carry,
+            FlagCondition::Negative => self.negative,
+            FlagCondition::NotNegative => !self.negative,
+            FlagCondition::Overflow => self.overflow,
+            FlagCondition::NotOverflow => !self.overflow,
+            FlagCondition::Less => self.negative != self.overflow,
+            FlagCondition::GreaterEqual => self.negative == self.overflow,
+            FlagCondition::LessEqual => self.zero || self.negative != self.overflow,
+            FlagCondition::Greater => !self.zero && self.negative == self.overflow,
+            FlagCondition::Above => !self.carry && !self.zero,
+            FlagCondition::BelowEqual => self.carry || self.zero
+        }
+    }
+}
+
+/// What runs when the program executes `syscall <number>`, arguments and results are passed in registers
+pub type Syscall<B> = Box<dyn FnMut(&mut Emulator<B>) -> Result<(), FaultKind>>;
+
+/// The cpu, everything outside the registers goes through `bus`
+pub struct Emulator<B: Bus = SystemBus> {
     registers: [u64; 16],
-    ram: [u8; RAM_SIZE]
+    flags: Flags,
+    bus: B,
+    halted: bool,
+    /// set by `exit`, 0 for a plain `halt`
+    exit_code: u64,
+    /// cycles gone by since the emulator was created
+    cycles: u64,
+    interrupts_enabled: bool,
+    /// where the interrupt vector table starts, set by `vectors`
+    vector_table: u64,
+    protection: Protection,
+    /// where the stack may grow, from its limit up to its base
+    stack: Range<u64>,
+    /// every range `load_at` copied into, the stack never grows over these
+    loaded: Vec<Range<u64>>,
+    syscalls: HashMap<u8, Syscall<B>>,
+    tracer: Option<Box<dyn TraceSink>>,
+    /// program counter after the last byte fetched for the current instruction
+    fetch_end: u64,
+    /// writes made during the current step, only filled while tracing
+    trace_writes: Vec<MemoryWrite>
 }
 
 impl Emulator {
+    /// An emulator with only `RAM_SIZE` bytes of ram and `bin` loaded at address 0, devices can be mapped with `bus_mut`
     pub fn new(bin: &[u8]) -> Emulator {
-        let mut ram = [0; RAM_SIZE];
+        Emulator::with_ram_size(bin, RAM_SIZE as u64)
+    }
 
-        for (i, byte) in bin.iter().enumerate() {
-            ram[i] = *byte;
-        }
+    /// Like `new` with `size` bytes of ram, it's allocated a page at a time as the program uses it
+    /// so sizes of several gigabytes are fine
+    ///
+    /// panics if `bin` doesn't fit
+    pub fn with_ram_size(bin: &[u8], size: u64) -> Emulator {
+        let mut emulator = Emulator::with_bus(SystemBus::with_ram_size(size));
+        emulator.load_at(0, bin).expect("binary doesn't fit in ram");
 
+        emulator
+    }
+}
+
+impl<B: Bus> Emulator<B> {
+    /// An emulator that reads and writes through `bus`, nothing is loaded and the stack starts at the top of its ram
+    pub fn with_bus(bus: B) -> Emulator<B> {
+        let ram_size = bus.ram_size();
         let mut registers = [0; 16];
-        registers[STACK_REG] = ram.len() as u64 - 1;
+        registers[STACK_REG] = ram_size.wrapping_sub(1);
 
         Emulator {
             registers,
-            ram
+            flags: Flags::default(),
+            bus,
+            halted: false,
+            exit_code: 0,
+            cycles: 0,
+            interrupts_enabled: false,
+            vector_table: 0,
+            protection: Protection::new(),
+            stack: 0..ram_size,
+            loaded: Vec::new(),
+            syscalls: HashMap::new(),
+            tracer: None,
+            fetch_end: 0,
+            trace_writes: Vec::new()
         }
     }
 
-    fn read_byte(&self, addr: usize) -> u8 {
-        self.ram[addr]
+    pub fn bus(&self) -> &B {
+        &self.bus
     }
 
-    fn read_dbyte(&self, addr: usize) -> u16 {
-        u16::from_be_bytes([
-            self.ram[addr],
-            self.ram[addr + 1]
-        ])
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
     }
 
-    fn read_qbyte(&self, addr: usize) -> u32 {
-        u32::from_be_bytes([
-            self.ram[addr],
-            self.ram[addr + 1],
-            self.ram[addr + 2],
-            self.ram[addr + 3]
-        ])
+    /// Report every step to `tracer` from now on, `None` turns tracing off
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn TraceSink>>) {
+        self.tracer = tracer;
     }
 
-    fn read_obyte(&self, addr: usize) -> u64 {
-        u64::from_be_bytes([
-            self.ram[addr],
-            self.ram[addr + 1],
-            self.ram[addr + 2],
-            self.ram[addr + 3],
-            self.ram[addr + 4],
-            self.ram[addr + 5],
-            self.ram[addr + 6],
-            self.ram[addr + 7]
-        ])
+    /// Value of register `idx`, `COUNTER_REG` and `STACK_REG` included
+    ///
+    /// panics if `idx` is 16 or more
+    pub fn register(&self, idx: usize) -> u64 {
+        self.registers[idx]
     }
 
-    fn read_next_byte(&mut self) -> u8 {
-        let byte = self.ram[self.registers[COUNTER_REG] as usize];
-        self.registers[COUNTER_REG] += 1;
-        byte
+    /// Overwrite register `idx`
+    ///
+    /// panics if `idx` is 16 or more
+    pub fn set_register(&mut self, idx: usize, value: u64) {
+        self.registers[idx] = value;
     }
 
-    fn read_next_dbyte(&mut self) -> u16 {
-        let byte1 = self.ram[self.registers[COUNTER_REG] as usize];
-        let byte2 = self.ram[self.registers[COUNTER_REG] as usize + 1];
-        self.registers[COUNTER_REG] += 2;
-        u16::from_be_bytes([byte1, byte2])
+    pub fn flags(&self) -> Flags {
+        self.flags
     }
 
-    fn read_next_qbyte(&mut self) -> u32 {
-        let byte1 = self.ram[self.registers[COUNTER_REG] as usize];
-        let byte2 = self.ram[self.registers[COUNTER_REG] as usize + 1];
-        let byte3 = self.ram[self.registers[COUNTER_REG] as usize + 2];
-        let byte4 = self.ram[self.registers[COUNTER_REG] as usize + 3];
-        self.registers[COUNTER_REG] += 4;
-        u32::from_be_bytes([byte1, byte2, byte3, byte4])
+    /// Look at a slice of ram, `None` if any of it is out of range or not plain memory
+    pub fn memory(&self, range: Range<u64>) -> Option<Cow<'_, [u8]>> {
+        self.bus.memory(range)
     }
 
-    fn read_next_obyte(&mut self) -> u64 {
-        let byte1 = self.ram[self.registers[COUNTER_REG] as usize];
-        let byte2 = self.ram[self.registers[COUNTER_REG] as usize + 1];
-        let byte3 = self.ram[self.registers[COUNTER_REG] as usize + 2];
-        let byte4 = self.ram[self.registers[COUNTER_REG] as usize + 3];
-        let byte5 = self.ram[self.registers[COUNTER_REG] as usize + 4];
-        let byte6 = self.ram[self.registers[COUNTER_REG] as usize + 5];
-        let byte7 = self.ram[self.registers[COUNTER_REG] as usize + 6];
-        let byte8 = self.ram[self.registers[COUNTER_REG] as usize + 7];
-        self.registers[COUNTER_REG] += 8;
-        u64::from_be_bytes([byte1, byte2, byte3, byte4, byte5, byte6, byte7, byte8])
+    /// Copy `bytes` into ram starting at `addr`, a push that would overwrite them faults
+    pub fn load_at(&mut self, addr: u64, bytes: &[u8]) -> Result<(), FaultKind> {
+        let end = addr.checked_add(bytes.len() as u64).ok_or(FaultKind::BadAddress(addr))?;
+        self.bus.load(addr, bytes)?;
+
+        if !bytes.is_empty() {
+            self.loaded.push(addr..end);
+        }
+
+        Ok(())
     }
 
-    fn write_byte(&mut self, addr: usize, byte: u8) {
-        self.ram[addr] = byte;
+    /// Copy `bytes` into ram like `load_at` and give them `permissions`, so a loader can keep a program
+    /// from overwriting its own code with `Permissions::READ | Permissions::EXECUTE`
+    pub fn load_at_with(&mut self, addr: u64, bytes: &[u8], permissions: Permissions) -> Result<(), FaultKind> {
+        self.load_at(addr, bytes)?;
+        self.protect(addr..addr + bytes.len() as u64, permissions);
+
+        Ok(())
     }
 
-    fn write_dbyte(&mut self, addr: usize, dbyte: u16) {
-        let bytes = dbyte.to_be_bytes();
+    /// Put the stack in `range` and empty it, the stack pointer goes to the byte below `range.end`
+    ///
+    /// the stack starts as all of ram, a push below `range.start` faults with `StackOverflow`
+    /// and a pop above `range.end` with `StackUnderflow`
+    pub fn set_stack(&mut self, range: Range<u64>) {
+        self.registers[STACK_REG] = range.end.wrapping_sub(1);
+        self.stack = range;
+    }
 
-        self.ram[addr] = bytes[0];
-        self.ram[addr + 1] = bytes[1];
+    /// Where the stack may grow, from its limit up to its base
+    pub fn stack(&self) -> Range<u64> {
+        self.stack.clone()
     }
 
-    fn write_qbyte(&mut self, addr: usize, qbyte: u32) {
-        let bytes = qbyte.to_be_bytes();
+    /// Whether the program has executed `halt` or exited
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
 
-        self.ram[addr] = bytes[0];
-        self.ram[addr + 1] = bytes[1];
-        self.ram[addr + 2] = bytes[2];
-        self.ram[addr + 3] = bytes[3];
+    /// The code the program exited with, 0 if it hasn't exited or stopped with `halt`
+    pub fn exit_code(&self) -> u64 {
+        self.exit_code
     }
 
-    fn write_obyte(&mut self, addr: usize, obyte: u64) {
-        let bytes = obyte.to_be_bytes();
+    /// Stop the program like `halt` does but with an exit code, for syscall handlers
+    pub fn exit(&mut self, code: u64) {
+        self.halted = true;
+        self.exit_code = code;
+    }
 
-        self.ram[addr] = bytes[0];
-        self.ram[addr + 1] = bytes[1];
-        self.ram[addr + 2] = bytes[2];
-        self.ram[addr + 3] = bytes[3];
-        self.ram[addr + 4] = bytes[4];
-        self.ram[addr + 5] = bytes[5];
-        self.ram[addr + 6] = bytes[6];
-        self.ram[addr + 7] = bytes[7];
+    /// Cycles gone by since the emulator was created, every instruction costs the `cycles` in its
+    /// `InstructionDef` and taking an interrupt costs `INTERRUPT_CYCLES`
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Whether a raised interrupt line would be taken, programs start with interrupts off
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    /// Run `handler` whenever the program executes `syscall <number>`, replacing any handler already there
+    pub fn set_syscall(&mut self, number: u8, handler: Syscall<B>) {
+        self.syscalls.insert(number, handler);
+    }
+
+    /// Give `range` the `permissions`, overriding whatever was set for it before
+    ///
+    /// the cpu faults on any fetch, read or write that isn't allowed, the host itself can
+    /// still change protected memory with `load_at` and devices can with dma
+    pub fn protect(&mut self, range: Range<u64>, permissions: Permissions) {
+        self.protection.protect(range, permissions);
+    }
+
+    pub fn protection(&self) -> &Protection {
+        &self.protection
+    }
+
+    /// Fault unless `access` is allowed on all `len` bytes from `addr`
+    fn check_access(&self, addr: u64, len: usize, access: Access) -> Result<(), FaultKind> {
+        self.protection.check(addr, len, access).map_err(|address| FaultKind::Protection {
+            address,
+            access
+        })
+    }
+
+    /// Fill `buf` from the bus starting at `addr` as if the program did it, for syscall handlers
+    pub fn read_bytes(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), FaultKind> {
+        self.check_access(addr, buf.len(), Access::Read)?;
+        self.bus.read(addr, buf)
+    }
+
+    /// Write `bytes` to the bus starting at `addr` as if the program did it, for syscall handlers
+    pub fn write_bytes(&mut self, addr: u64, bytes: &[u8]) -> Result<(), FaultKind> {
+        self.check_access(addr, bytes.len(), Access::Write)?;
+        self.bus.write(addr, bytes)?;
+        self.record_write(addr, bytes);
+
+        Ok(())
+    }
+
+    /// Read a big endian value of `bytes` bytes
+    fn read(&mut self, addr: u64, bytes: usize) -> Result<u64, FaultKind> {
+        let mut value_bytes = [0; 8];
+        self.read_bytes(addr, &mut value_bytes[8 - bytes..])?;
+
+        Ok(u64::from_be_bytes(value_bytes))
+    }
+
+    /// Write the low `bytes` bytes of `value` big endian
+    fn write(&mut self, addr: u64, value: u64, bytes: usize) -> Result<(), FaultKind> {
+        self.write_bytes(addr, &value.to_be_bytes()[8 - bytes..])
+    }
+
+    fn record_write(&mut self, address: u64, bytes: &[u8]) {
+        if self.tracer.is_some() {
+            self.trace_writes.push(MemoryWrite {
+                address,
+                bytes: bytes.to_vec()
+            });
+        }
     }
 
     /// Push a value onto the stack
-    /// 
+    ///
     /// `param` - value to push
-    /// 
+    ///
     /// `bytes` - number of bytes to push
-    fn push(&mut self, value: u64, bytes: usize) {
+    fn push(&mut self, value: u64, bytes: usize) -> Result<(), FaultKind> {
         let value_bytes = value.to_le_bytes();
 
-        let value_offset = self.registers[STACK_REG] as usize - bytes + 1;
+        let value_offset = self.registers[STACK_REG].wrapping_add(1).checked_sub(bytes as u64).ok_or(FaultKind::StackOverflow)?;
+        let value_end = value_offset + bytes as u64;
 
-        for i in 0..bytes {
-            self.ram[value_offset + i] = value_bytes[i];
+        // the stack pointer can be moved anywhere, so the value has to land inside the stack on both ends
+        let into_loaded = self.loaded.iter().any(|loaded| value_offset < loaded.end && loaded.start < value_end);
+        if value_offset < self.stack.start || value_end > self.stack.end || into_loaded {
+            return Err(FaultKind::StackOverflow);
         }
 
+        self.write_bytes(value_offset, &value_bytes[..bytes])?;
+
         self.registers[STACK_REG] -= bytes as u64;
+
+        Ok(())
     }
 
-    fn pop(&mut self, bytes: usize) -> u64 {
+    fn pop(&mut self, bytes: usize) -> Result<u64, FaultKind> {
         let mut bytes_read = [0; 8];
 
-        for i in 0..bytes {
-            bytes_read[i] = self.ram[self.registers[STACK_REG] as usize + i + 1];
+        let top = self.registers[STACK_REG].checked_add(1).ok_or(FaultKind::StackUnderflow)?;
+        if top < self.stack.start || top.checked_add(bytes as u64).is_none_or(|end| end > self.stack.end) {
+            return Err(FaultKind::StackUnderflow);
         }
 
+        // anything past the bounds check is the bus's fault, not an underflow
+        self.read_bytes(top, &mut bytes_read[..bytes])?;
+
         self.registers[STACK_REG] += bytes as u64;
 
-        u64::from_le_bytes(bytes_read)
+        Ok(u64::from_le_bytes(bytes_read))
     }
 
-    pub fn run(&mut self) {
+    /// Run until the program halts, faults or `budget` runs out
+    pub fn run(&mut self, budget: Budget) -> RunOutcome {
+        let mut steps = 0;
+        let start_cycles = self.cycles;
+
         loop {
-            let instruction = self.read_next_byte();
+            if self.halted {
+                return RunOutcome::Halted;
+            }
 
-            match instruction {
-                0 => {}
-                // move <register> <register>
-                1 => {
-                    let io_registers = self.read_next_byte();
-                    let input_register = (io_registers & 0b1111_0000) >> 4;
-                    let output_register = io_registers & 0b0000_1111;
+            match budget {
+                Budget::Steps(max_steps) if steps >= max_steps => return RunOutcome::StepLimit,
+                Budget::Cycles(max_cycles) if self.cycles - start_cycles >= max_cycles => return RunOutcome::CycleLimit,
+                _ => {}
+            }
+            steps += 1;
 
-                    self.registers[input_register as usize] = self.registers[output_register as usize];
-                }
-                // move <type> <register> <value>
-                2 => {
-                    let specified_type = self.read_next_byte();
-                    let register = self.read_next_byte() as usize;
-                    let value = match specified_type {
-                        0 => self.read_next_byte() as u64,
-                        1 => self.read_next_dbyte() as u64,
-                        2 => self.read_next_qbyte() as u64,
-                        3 => self.read_next_obyte() as u64,
-                        _ => {unreachable!()}
-                    };
-
-                    self.registers[register] = value;
-                }
-                // read <type> <register> <address>
-                3 => {
-                    let specified_type = self.read_next_byte();
-                    let register = self.read_next_byte() as usize;
-                    let address = self.read_next_obyte() as usize;
-
-                    self.registers[register] = match specified_type {
-                        0 => self.read_byte(address) as u64,
-                        1 => self.read_dbyte(address) as u64,
-                        2 => self.read_qbyte(address) as u64,
-                        3 => self.read_obyte(address) as u64,
-                        _ => {unreachable!()}
-                    }
-                }
-                // read <type> <register> <register>
-                4 => {
-                    let specified_type = self.read_next_byte();
-                    let registers = self.read_next_byte() as usize;
-                    let input_register = (registers & 0b1111_0000) >> 4;
-                    let address_register = (registers & 0b0000_1111) as usize;
-
-                    match specified_type {
-                        0 => {
-                            self.registers[input_register] = self.read_byte(self.registers[address_register] as usize) as u64;
-                        }
-                        1 => {
-                            self.registers[input_register] = self.read_dbyte(self.registers[address_register] as usize) as u64;
-                        }
-                        2 => {
-                            self.registers[input_register] = self.read_qbyte(self.registers[address_register] as usize) as u64;
-                        }
-                        3 => {
-                            self.registers[input_register] = self.read_obyte(self.registers[address_register] as usize) as u64;
-                        }
-                        _ => {}
-                    }
-                }
-                // write <type> <register> <address>
-                5 => {
-                    let specified_type = self.read_next_byte();
-                    let register = self.read_next_byte() as usize;
-                    let address = self.read_next_obyte() as usize;
-
-                    match specified_type {
-                        0 => {
-                            self.write_byte(address, self.registers[register] as u8);
-                        }
-                        1 => {
-                            self.write_dbyte(address, self.registers[register] as u16);
-                        }
-                        2 => {
-                            self.write_qbyte(address, self.registers[register] as u32);
-                        }
-                        3 => {
-                            self.write_obyte(address, self.registers[register] as u64);
-                        }
-                        _ => {}
-                    }
-                }
-                // write <type> <register> <register>
-                6 => {
-                    let specified_type = self.read_next_byte();
-                    let registers = self.read_next_byte();
-                    let output_register = ((registers & 0b1111_0000) >> 4) as usize;
-                    let address_register = (registers & 0b0000_1111) as usize;
-
-                    match specified_type {
-                        0 => {
-                            self.write_byte(self.registers[address_register] as usize, self.registers[output_register] as u8);
-                        }
-                        1 => {
-                            self.write_dbyte(self.registers[address_register] as usize, self.registers[output_register] as u16);
-                        }
-                        2 => {
-                            self.write_qbyte(self.registers[address_register] as usize, self.registers[output_register] as u32);
-                        }
-                        3 => {
-                            self.write_obyte(self.registers[address_register] as usize, self.registers[output_register] as u64);
-                        }
-                        _ => {}
-                    }
-                }
-                // push <type> <register>
-                7 => {
-                    let specified_type = self.read_next_byte();
-                    let register = self.read_next_byte() as usize;
-
-                    match specified_type {
-                        0 => {
-                            self.push(self.registers[register], 1);
-                        }
-                        1 => {
-                            self.push(self.registers[register], 2);
-                        }
-                        2 => {
-                            self.push(self.registers[register], 4);
-                        }
-                        3 => {
-                            self.push(self.registers[register], 8);
-                        }
-                        _ => {}
-                    }
-                }
-                // push <type> <value>
-                8 => {
-                    let specified_type = self.read_next_byte();
-
-                    match specified_type {
-                        0 => {
-                            let value = self.read_next_byte() as u64;
-                            self.push(value, 1);
-                        }
-                        1 => {
-                            let value = self.read_next_dbyte() as u64;
-                            self.push(value, 2);
-                        }
-                        2 => {
-                            let value = self.read_next_qbyte() as u64;
-                            self.push(value, 4);
-                        }
-                        3 => {
-                            let value = self.read_next_obyte() as u64;
-                            self.push(value, 8);
-                        }
-                        _ => {}
-                    }
-                }
-                // pop <type> <register>
-                9 => {
-                    let specified_type = self.read_next_byte();
-                    let register = self.read_next_byte() as usize;
-
-                    match specified_type {
-                        0 => {
-                            let value = self.pop(1);
-                            self.registers[register] = value;
-                        }
-                        1 => {
-                            let value = self.pop(2);
-                            self.registers[register] = value;
-                        }
-                        2 => {
-                            let value = self.pop(4);
-                            self.registers[register] = value;
-                        }
-                        3 => {
-                            let value = self.pop(8);
-                            self.registers[register] = value;
-                        }
-                        _ => {}
-                    }
-                }
-                // jump <address/label> technically label is an address
-                10 => {
-                    let address = self.read_next_obyte() as u64;
+            if let Err(fault) = self.step() {
+                return RunOutcome::Fault(fault);
+            }
+        }
+    }
 
-                    self.registers[COUNTER_REG] = address;
-                }
-                // jump <register>
-                11 => {
-                    let register = self.read_next_byte() as usize;
+    /// Fetch and execute one instruction
+    ///
+    /// on a fault the program counter is put back on the faulting instruction,
+    /// so the same fault comes back if it is stepped again
+    pub fn step(&mut self) -> Result<(), Fault> {
+        if self.halted {
+            return Ok(());
+        }
 
-                    self.registers[COUNTER_REG] = self.registers[register];
-                }
-                // jump <address/label> <condition>
-                12 => {
-                    let condition = self.read_next_byte();
-
-                    let address = self.read_next_obyte() as u64;
-                    
-                    if condition as u64 == self.registers[2] {
-                        self.registers[COUNTER_REG] = address;
-                    }
-                }
-                // jump <register> <condition>
-                13 => {
-                    let condition = self.read_next_byte();
-
-                    let register = self.read_next_byte() as usize;
-                    
-                    if condition as u64 == self.registers[2] {
-                        self.registers[COUNTER_REG] = self.registers[register as usize];
-                    }
-                }
-                // add
-                14 => {
-                    self.registers[2] = self.registers[0] + self.registers[1];
-                }
-                // sub
-                15 => {
-                    self.registers[2] = self.registers[0] - self.registers[1];
-                }
-                // mul
-                16 => {
-                    self.registers[2] = self.registers[0] * self.registers[1];
-                }
-                // div
-                17 => {
-                    self.registers[2] = self.registers[0] / self.registers[1];
-                    self.registers[3] = self.registers[0] % self.registers[1];
-                }
-                // equal
-                18 => {
-                    self.registers[2] = (self.registers[0] == self.registers[1]) as u64;
-                }
-                // less
-                19 => {
-                    self.registers[2] = (self.registers[0] < self.registers[1]) as u64;
+        if self.tracer.is_none() {
+            let interrupt_cycles = self.interrupt()?;
+            let result = self.fetch_execute();
+            self.tick(interrupt_cycles + result.as_ref().map_or(0, |cycles| *cycles));
+
+            return result.map(|_| ());
+        }
+
+        // taken ahead of the interrupt, so entering a handler shows up in the same step as its first instruction
+        let registers_before = self.registers;
+        self.trace_writes.clear();
+
+        let (pc, result) = match self.interrupt() {
+            Ok(interrupt_cycles) => {
+                let pc = self.registers[COUNTER_REG];
+                let result = self.fetch_execute();
+                self.tick(interrupt_cycles + result.as_ref().map_or(0, |cycles| *cycles));
+
+                (pc, result.map(|_| ()))
+            }
+            Err(fault) => {
+                self.fetch_end = fault.pc;
+                (fault.pc, Err(fault))
+            }
+        };
+
+        let event = TraceEvent {
+            pc,
+            bytes: self.memory(pc..self.fetch_end).map(Cow::into_owned).unwrap_or_default(),
+            registers: changed_registers(&registers_before, &self.registers, self.fetch_end),
+            memory: std::mem::take(&mut self.trace_writes),
+            fault: result.err()
+        };
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&event);
+        }
+
+        result
+    }
+
+    /// Let `cycles` go by for the cpu and every device
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        self.bus.tick(cycles);
+    }
+
+    /// Take the lowest raised interrupt line if interrupts are on, returns the cycles it took
+    ///
+    /// the program counter and then the flags get pushed, interrupts are turned off and the
+    /// program counter is set to the line's obyte entry in the vector table
+    fn interrupt(&mut self) -> Result<u64, Fault> {
+        if !self.interrupts_enabled {
+            return Ok(0);
+        }
+
+        let line = match self.bus.irq() {
+            Some(line) if line < IRQ_LINES => line,
+            _ => {
+                return Ok(0);
+            }
+        };
+
+        let pc = self.registers[COUNTER_REG];
+        let entry = self.vector_table.wrapping_add(8 * line as u64);
+
+        let sp = self.registers[STACK_REG];
+        let result = self.read(entry, 8).and_then(|handler| {
+            self.push(pc, 8)?;
+            self.push(self.flags.to_byte() as u64, 1)?;
+
+            Ok(handler)
+        });
+
+        match result {
+            Ok(handler) => {
+                self.interrupts_enabled = false;
+                self.registers[COUNTER_REG] = handler;
+
+                Ok(INTERRUPT_CYCLES)
+            }
+            Err(kind) => {
+                // the pc may have fit when the flags didn't, the interrupt either happens whole or not at all
+                self.registers[STACK_REG] = sp;
+
+                Err(Fault {
+                    pc,
+                    opcode: None,
+                    kind
+                })
+            }
+        }
+    }
+
+    /// Returns the cycles the instruction took
+    fn fetch_execute(&mut self) -> Result<u64, Fault> {
+        let pc = self.registers[COUNTER_REG];
+        self.fetch_end = pc;
+
+        let result = self.fetch().and_then(|(instruction, def)| {
+            self.execute(instruction)?;
+            Ok(def.cycles)
+        });
+
+        result.map_err(|kind| {
+            self.registers[COUNTER_REG] = pc;
+
+            Fault {
+                pc,
+                opcode: self.memory(pc..pc.wrapping_add(1)).map(|opcode| opcode[0]),
+                kind
+            }
+        })
+    }
+
+    /// Decode the instruction at the program counter and move past it, returns it along with its definition
+    ///
+    /// instructions are only fetched from plain memory, never from a device
+    fn fetch(&mut self) -> Result<(Instruction, &'static InstructionDef), FaultKind> {
+        let pc = self.registers[COUNTER_REG];
+
+        // the instruction may be shorter than the longest one, so take as much as there is
+        let bytes = (1..=MAX_INSTRUCTION_LEN as u64)
+            .rev()
+            .find_map(|len| self.bus.memory(pc..pc.checked_add(len)?))
+            .ok_or(FaultKind::BadAddress(pc))?;
+        let (instruction, len) = decode(&bytes).map_err(|error| FaultKind::from_decode(error, pc + bytes.len() as u64))?;
+        self.check_access(pc, len, Access::Execute)?;
+
+        self.registers[COUNTER_REG] += len as u64;
+        self.fetch_end = self.registers[COUNTER_REG];
+
+        // decode already found it, so this is just a table lookup
+        let def = InstructionDef::get(bytes[0]).expect("decoded instructions have a definition");
+
+        Ok((instruction, def))
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), FaultKind> {
+        match instruction {
+            Instruction::Nop => {}
+            Instruction::MoveRegister { dst, src } => {
+                self.registers[dst as usize] = self.registers[src as usize];
+            }
+            Instruction::MoveValue { register, value, .. } => {
+                self.registers[register as usize] = value;
+            }
+            Instruction::ReadAddress { ty, register, address } => {
+                self.registers[register as usize] = self.read(address, ty.size())?;
+            }
+            Instruction::ReadRegister { ty, register, address_register } => {
+                self.registers[register as usize] = self.read(self.registers[address_register as usize], ty.size())?;
+            }
+            Instruction::ReadSignedAddress { ty, register, address } => {
+                self.registers[register as usize] = ty.sign_extend(self.read(address, ty.size())?);
+            }
+            Instruction::ReadSignedRegister { ty, register, address_register } => {
+                self.registers[register as usize] = ty.sign_extend(self.read(self.registers[address_register as usize], ty.size())?);
+            }
+            Instruction::WriteAddress { ty, register, address } => {
+                self.write(address, self.registers[register as usize], ty.size())?;
+            }
+            Instruction::WriteRegister { ty, register, address_register } => {
+                self.write(self.registers[address_register as usize], self.registers[register as usize], ty.size())?;
+            }
+            Instruction::PushRegister { ty, register } => {
+                self.push(self.registers[register as usize], ty.size())?;
+            }
+            Instruction::PushValue { ty, value } => {
+                self.push(value, ty.size())?;
+            }
+            Instruction::Pop { ty, register } => {
+                self.registers[register as usize] = self.pop(ty.size())?;
+            }
+            Instruction::Jump { address } => {
+                self.registers[COUNTER_REG] = address;
+            }
+            Instruction::JumpRegister { register } => {
+                self.registers[COUNTER_REG] = self.registers[register as usize];
+            }
+            Instruction::JumpIf { condition, address } => {
+                if condition as u64 == self.registers[2] {
+                    self.registers[COUNTER_REG] = address;
                 }
-                // not
-                20 => {
-                    self.registers[2] = !self.registers[0];
+            }
+            Instruction::JumpRegisterIf { condition, register } => {
+                if condition as u64 == self.registers[2] {
+                    self.registers[COUNTER_REG] = self.registers[register as usize];
                 }
-                // and
-                21 => {
-                    self.registers[2] = self.registers[0] & self.registers[1];
+            }
+            Instruction::Add => {
+                self.registers[2] = self.alu(AluOp::Add, self.registers[0], self.registers[1])?;
+            }
+            Instruction::Sub => {
+                self.registers[2] = self.alu(AluOp::Sub, self.registers[0], self.registers[1])?;
+            }
+            Instruction::Mul => {
+                self.registers[2] = self.alu(AluOp::Mul, self.registers[0], self.registers[1])?;
+            }
+            Instruction::Div => {
+                self.registers[2] = self.alu(AluOp::Div, self.registers[0], self.registers[1])?;
+                self.registers[3] = self.registers[0] % self.registers[1];
+            }
+            Instruction::Equal => {
+                self.registers[2] = self.alu(AluOp::Equal, self.registers[0], self.registers[1])?;
+            }
+            Instruction::Less => {
+                self.registers[2] = self.alu(AluOp::Less, self.registers[0], self.registers[1])?;
+            }
+            Instruction::Not => {
+                self.registers[2] = !self.registers[0];
+                self.flags = Flags::from_result(self.registers[2]);
+            }
+            Instruction::And => {
+                self.registers[2] = self.alu(AluOp::And, self.registers[0], self.registers[1])?;
+            }
+            Instruction::Or => {
+                self.registers[2] = self.alu(AluOp::Or, self.registers[0], self.registers[1])?;
+            }
+            Instruction::Xor => {
+                self.registers[2] = self.alu(AluOp::Xor, self.registers[0], self.registers[1])?;
+            }
+            Instruction::Halt => {
+                self.halted = true;
+            }
+            Instruction::Call { address } => {
+                self.push(self.registers[COUNTER_REG], 8)?;
+                self.registers[COUNTER_REG] = address;
+            }
+            Instruction::CallRegister { register } => {
+                let address = self.registers[register as usize];
+                self.push(self.registers[COUNTER_REG], 8)?;
+                self.registers[COUNTER_REG] = address;
+            }
+            Instruction::Ret => {
+                self.registers[COUNTER_REG] = self.pop(8)?;
+            }
+            Instruction::Syscall { number } => {
+                // the handler gets the whole emulator, so it's taken out of the table while it runs
+                let mut handler = self.syscalls.remove(&number).ok_or(FaultKind::BadSyscall(number))?;
+                let result = handler(self);
+                self.syscalls.entry(number).or_insert(handler);
+
+                result?;
+            }
+            Instruction::Iret => {
+                self.flags = Flags::from_byte(self.pop(1)? as u8);
+                self.registers[COUNTER_REG] = self.pop(8)?;
+                self.interrupts_enabled = true;
+            }
+            Instruction::EnableInterrupts => {
+                self.interrupts_enabled = true;
+            }
+            Instruction::DisableInterrupts => {
+                self.interrupts_enabled = false;
+            }
+            Instruction::Vectors { address } => {
+                self.vector_table = address;
+            }
+            Instruction::VectorsRegister { register } => {
+                self.vector_table = self.registers[register as usize];
+            }
+            Instruction::NotRegister { dst, src } => {
+                self.registers[dst as usize] = !self.registers[src as usize];
+                self.flags = Flags::from_result(self.registers[dst as usize]);
+            }
+            Instruction::Neg { dst, src } => {
+                self.registers[dst as usize] = self.alu(AluOp::Sub, 0, self.registers[src as usize])?;
+            }
+            Instruction::JumpFlags { condition, address } => {
+                if self.flags.test(condition) {
+                    self.registers[COUNTER_REG] = address;
                 }
-                // or
-                22 => {
-                    self.registers[2] = self.registers[0] | self.registers[1];
+            }
+            Instruction::JumpRegisterFlags { condition, register } => {
+                if self.flags.test(condition) {
+                    self.registers[COUNTER_REG] = self.registers[register as usize];
                 }
-                // xor
-                23 => {
-                    self.registers[2] = self.registers[0] ^ self.registers[1];
+            }
+            Instruction::AluRegister { op, dst, src1, src2 } => {
+                self.registers[dst as usize] = self.alu(op, self.registers[src1 as usize], self.registers[src2 as usize])?;
+            }
+            Instruction::AluValue { op, dst, src, value, .. } => {
+                self.registers[dst as usize] = self.alu(op, self.registers[src as usize], value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `a <op> b` and set the flags from it, add, sub and mul wrap around and div faults if `b` is 0
+    ///
+    /// the compares set the flags like sub does, so a flag jump can follow any of them
+    fn alu(&mut self, op: AluOp, a: u64, b: u64) -> Result<u64, FaultKind> {
+        let (result, flags) = match op {
+            AluOp::Add => {
+                let (result, carry) = a.overflowing_add(b);
+                (result, Flags { carry, overflow: (a as i64).overflowing_add(b as i64).1, ..Flags::from_result(result) })
+            }
+            AluOp::Sub | AluOp::Equal | AluOp::Less | AluOp::LessSigned | AluOp::Greater | AluOp::GreaterSigned => {
+                let (difference, carry) = a.overflowing_sub(b);
+                let flags = Flags { carry, overflow: (a as i64).overflowing_sub(b as i64).1, ..Flags::from_result(difference) };
+
+                match op {
+                    AluOp::Equal => ((a == b) as u64, flags),
+                    AluOp::Less => ((a < b) as u64, flags),
+                    AluOp::LessSigned => (((a as i64) < (b as i64)) as u64, flags),
+                    AluOp::Greater => ((a > b) as u64, flags),
+                    AluOp::GreaterSigned => (((a as i64) > (b as i64)) as u64, flags),
+                    _ => (difference, flags)
                 }
-                _ => {}
             }
-            
-            println!("a: {}, f: {}", self.registers[0], self.registers[5]);
+            AluOp::Mul => {
+                let (result, carry) = a.overflowing_mul(b);
+                (result, Flags { carry, overflow: (a as i64).overflowing_mul(b as i64).1, ..Flags::from_result(result) })
+            }
+            AluOp::Div => {
+                let result = a.checked_div(b).ok_or(FaultKind::DivideByZero)?;
+                (result, Flags::from_result(result))
+            }
+            AluOp::Rem => {
+                let result = a.checked_rem(b).ok_or(FaultKind::DivideByZero)?;
+                (result, Flags::from_result(result))
+            }
+            // the smallest number divided by -1 is the one case that overflows
+            AluOp::DivSigned | AluOp::RemSigned => {
+                if b == 0 {
+                    return Err(FaultKind::DivideByZero);
+                }
 
-            if self.registers[COUNTER_REG] > 50 {
-                break;
+                let (result, overflow) = match op {
+                    AluOp::DivSigned => (a as i64).overflowing_div(b as i64),
+                    _ => (a as i64).overflowing_rem(b as i64)
+                };
+                (result as u64, Flags { overflow, ..Flags::from_result(result as u64) })
             }
-        }
+            // shifting by 64 or more shifts everything out, carry is the last bit shifted out
+            AluOp::ShiftLeft => {
+                let result = if b < 64 { a << b } else { 0 };
+                let carry = (1..=64).contains(&b) && bit(a, 64 - b);
+                (result, Flags { carry, ..Flags::from_result(result) })
+            }
+            AluOp::ShiftRight => {
+                let result = if b < 64 { a >> b } else { 0 };
+                let carry = b != 0 && bit(a, b - 1);
+                (result, Flags { carry, ..Flags::from_result(result) })
+            }
+            AluOp::ShiftRightSigned => {
+                let result = ((a as i64) >> b.min(63)) as u64;
+                let carry = b != 0 && bit(a, b.min(64) - 1);
+                (result, Flags { carry, ..Flags::from_result(result) })
+            }
+            // rotating only looks at the bottom 6 bits of the count
+            AluOp::RotateLeft => {
+                let result = a.rotate_left((b % 64) as u32);
+                (result, Flags::from_result(result))
+            }
+            AluOp::RotateRight => {
+                let result = a.rotate_right((b % 64) as u32);
+                (result, Flags::from_result(result))
+            }
+            AluOp::And => (a & b, Flags::from_result(a & b)),
+            AluOp::Or => (a | b, Flags::from_result(a | b)),
+            AluOp::Xor => (a ^ b, Flags::from_result(a ^ b))
+        };
+
+        self.flags = flags;
+        Ok(result)
     }
 }
+
+/// Whether bit `n` of `value` is set, bits past the top are never set
+fn bit(value: u64, n: u64) -> bool {
+    n < 64 && (value >> n) & 1 == 1
+}
@@ -0,0 +1,859 @@
+use std::{fmt, sync::OnceLock};
+
+/// Names the assembler uses for each register, the index is the register number
+pub const REGISTER_NAMES: [&str; 16] = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "pc", "sp"];
+
+/// Size of the longest encoded instruction
+pub const MAX_INSTRUCTION_LEN: usize = 11;
+
+pub fn register_from_name(name: &str) -> Option<u8> {
+    REGISTER_NAMES.iter().position(|register| *register == name).map(|register| register as u8)
+}
+
+/// The `<type>` operand, how many bytes an instruction works with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Byte,
+    DByte,
+    QByte,
+    OByte
+}
+
+impl DataType {
+    pub fn from_index(index: u8) -> Option<DataType> {
+        match index {
+            0 => Some(DataType::Byte),
+            1 => Some(DataType::DByte),
+            2 => Some(DataType::QByte),
+            3 => Some(DataType::OByte),
+            _ => {None}
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DataType> {
+        match name {
+            "byte" => Some(DataType::Byte),
+            "dbyte" => Some(DataType::DByte),
+            "qbyte" => Some(DataType::QByte),
+            "obyte" => Some(DataType::OByte),
+            _ => {None}
+        }
+    }
+
+    pub fn index(self) -> u8 {
+        match self {
+            DataType::Byte => 0,
+            DataType::DByte => 1,
+            DataType::QByte => 2,
+            DataType::OByte => 3
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            DataType::Byte => 1,
+            DataType::DByte => 2,
+            DataType::QByte => 4,
+            DataType::OByte => 8
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DataType::Byte => "byte",
+            DataType::DByte => "dbyte",
+            DataType::QByte => "qbyte",
+            DataType::OByte => "obyte"
+        }
+    }
+
+    /// Largest value that fits in this many bytes
+    pub fn max_value(self) -> u64 {
+        u64::MAX >> (64 - 8 * self.size())
+    }
+
+    /// What `read` calls this type when it sign extends, like `sbyte`
+    pub fn signed_name(self) -> &'static str {
+        match self {
+            DataType::Byte => "sbyte",
+            DataType::DByte => "sdbyte",
+            DataType::QByte => "sqbyte",
+            DataType::OByte => "sobyte"
+        }
+    }
+
+    /// Copy the top bit of a value this wide into the rest of the 64 bits
+    pub fn sign_extend(self, value: u64) -> u64 {
+        let shift = 64 - 8 * self.size() as u32;
+        (((value << shift) as i64) >> shift) as u64
+    }
+
+    /// Smallest type `value` fits in
+    pub fn smallest_for(value: u64) -> DataType {
+        [DataType::Byte, DataType::DByte, DataType::QByte]
+            .into_iter()
+            .find(|ty| value <= ty.max_value())
+            .unwrap_or(DataType::OByte)
+    }
+}
+
+/// A two input ALU operation, for the forms that name their registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equal,
+    Less,
+    And,
+    Or,
+    Xor,
+    LessSigned,
+    Greater,
+    GreaterSigned,
+    DivSigned,
+    Rem,
+    RemSigned,
+    ShiftLeft,
+    ShiftRight,
+    ShiftRightSigned,
+    RotateLeft,
+    RotateRight
+}
+
+/// What a flag jump tests, `Less` to `Greater` are for signed numbers and `Above` and
+/// `BelowEqual` for unsigned ones, `Carry` and `NotCarry` double as below and above or equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagCondition {
+    Zero,
+    NotZero,
+    Carry,
+    NotCarry,
+    Negative,
+    NotNegative,
+    Overflow,
+    NotOverflow,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Greater,
+    Above,
+    BelowEqual
+}
+
+impl FlagCondition {
+    pub const ALL: [FlagCondition; 14] = [
+        FlagCondition::Zero,
+        FlagCondition::NotZero,
+        FlagCondition::Carry,
+        FlagCondition::NotCarry,
+        FlagCondition::Negative,
+        FlagCondition::NotNegative,
+        FlagCondition::Overflow,
+        FlagCondition::NotOverflow,
+        FlagCondition::Less,
+        FlagCondition::GreaterEqual,
+        FlagCondition::LessEqual,
+        FlagCondition::Greater,
+        FlagCondition::Above,
+        FlagCondition::BelowEqual
+    ];
+
+    pub fn from_index(index: u8) -> Option<FlagCondition> {
+        FlagCondition::ALL.get(index as usize).copied()
+    }
+
+    pub fn from_name(name: &str) -> Option<FlagCondition> {
+        FlagCondition::ALL.into_iter().find(|condition| condition.name() == name)
+    }
+
+    /// `jz` gives `Zero` and so on
+    pub fn from_mnemonic(mnemonic: &str) -> Option<FlagCondition> {
+        mnemonic.strip_prefix('j').and_then(FlagCondition::from_name)
+    }
+
+    pub fn index(self) -> u8 {
+        FlagCondition::ALL.iter().position(|condition| *condition == self).unwrap() as u8
+    }
+
+    /// Name used after `jump <target>`, prefixed with `j` it's the mnemonic of the short form
+    pub fn name(self) -> &'static str {
+        match self {
+            FlagCondition::Zero => "z",
+            FlagCondition::NotZero => "nz",
+            FlagCondition::Carry => "c",
+            FlagCondition::NotCarry => "nc",
+            FlagCondition::Negative => "s",
+            FlagCondition::NotNegative => "ns",
+            FlagCondition::Overflow => "o",
+            FlagCondition::NotOverflow => "no",
+            FlagCondition::Less => "lt",
+            FlagCondition::GreaterEqual => "ge",
+            FlagCondition::LessEqual => "le",
+            FlagCondition::Greater => "gt",
+            FlagCondition::Above => "a",
+            FlagCondition::BelowEqual => "be"
+        }
+    }
+}
+
+impl AluOp {
+    pub const ALL: [AluOp; 20] = [
+        AluOp::Add,
+        AluOp::Sub,
+        AluOp::Mul,
+        AluOp::Div,
+        AluOp::Equal,
+        AluOp::Less,
+        AluOp::And,
+        AluOp::Or,
+        AluOp::Xor,
+        AluOp::LessSigned,
+        AluOp::Greater,
+        AluOp::GreaterSigned,
+        AluOp::DivSigned,
+        AluOp::Rem,
+        AluOp::RemSigned,
+        AluOp::ShiftLeft,
+        AluOp::ShiftRight,
+        AluOp::ShiftRightSigned,
+        AluOp::RotateLeft,
+        AluOp::RotateRight
+    ];
+
+    pub fn from_index(index: u8) -> Option<AluOp> {
+        AluOp::ALL.get(index as usize).copied()
+    }
+
+    pub fn from_name(name: &str) -> Option<AluOp> {
+        AluOp::ALL.into_iter().find(|op| op.name() == name)
+    }
+
+    pub fn index(self) -> u8 {
+        AluOp::ALL.iter().position(|op| *op == self).unwrap() as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AluOp::Add => "add",
+            AluOp::Sub => "sub",
+            AluOp::Mul => "mul",
+            AluOp::Div => "div",
+            AluOp::Equal => "equal",
+            AluOp::Less => "less",
+            AluOp::And => "and",
+            AluOp::Or => "or",
+            AluOp::Xor => "xor",
+            AluOp::LessSigned => "less_signed",
+            AluOp::Greater => "greater",
+            AluOp::GreaterSigned => "greater_signed",
+            AluOp::DivSigned => "div_signed",
+            AluOp::Rem => "rem",
+            AluOp::RemSigned => "rem_signed",
+            AluOp::ShiftLeft => "shl",
+            AluOp::ShiftRight => "shr",
+            AluOp::ShiftRightSigned => "sar",
+            AluOp::RotateLeft => "rol",
+            AluOp::RotateRight => "ror"
+        }
+    }
+}
+
+/// How an operand is laid out after the opcode byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    /// one `<type>` byte
+    Type,
+    /// one `<type>` byte for a value that gets sign extended
+    SignedType,
+    /// one byte holding a register number
+    Register,
+    /// one byte with a register in the high nibble and another in the low nibble
+    RegisterPair,
+    /// big endian value as wide as the `Type` operand before it
+    Value,
+    /// 8 byte big endian address
+    Address,
+    /// one byte, 1 for true and 0 for false
+    Condition,
+    /// one byte, the index of a `FlagCondition`
+    FlagCondition,
+    /// one plain byte
+    Byte
+}
+
+impl OperandKind {
+    /// Encoded size, `ty` is only needed for `Value`
+    pub fn size(self, ty: Option<DataType>) -> usize {
+        match self {
+            OperandKind::Type |
+            OperandKind::SignedType |
+            OperandKind::Register |
+            OperandKind::RegisterPair |
+            OperandKind::Condition |
+            OperandKind::FlagCondition |
+            OperandKind::Byte => 1,
+            OperandKind::Value => ty.map_or(8, DataType::size),
+            OperandKind::Address => 8
+        }
+    }
+}
+
+/// A decoded operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Type(DataType),
+    SignedType(DataType),
+    Register(u8),
+    RegisterPair(u8, u8),
+    Value(u64),
+    Address(u64),
+    Condition(bool),
+    FlagCondition(FlagCondition),
+    Byte(u8)
+}
+
+impl Operand {
+    pub fn kind(&self) -> OperandKind {
+        match self {
+            Operand::Type(_) => OperandKind::Type,
+            Operand::SignedType(_) => OperandKind::SignedType,
+            Operand::Register(_) => OperandKind::Register,
+            Operand::RegisterPair(..) => OperandKind::RegisterPair,
+            Operand::Value(_) => OperandKind::Value,
+            Operand::Address(_) => OperandKind::Address,
+            Operand::Condition(_) => OperandKind::Condition,
+            Operand::FlagCondition(_) => OperandKind::FlagCondition,
+            Operand::Byte(_) => OperandKind::Byte
+        }
+    }
+}
+
+/// Everything there is to know about one opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionDef {
+    pub mnemonic: &'static str,
+    pub opcode: u8,
+    /// operands in the order they are encoded
+    pub operands: &'static [OperandKind],
+    /// how many cycles the instruction takes, memory access and division are the slow ones
+    pub cycles: u64
+}
+
+impl InstructionDef {
+    /// Encoded size including the opcode, `ty` is only needed for instructions with a `Value`
+    pub fn encoded_len(&self, ty: Option<DataType>) -> usize {
+        1 + self.operands.iter().map(|operand| operand.size(ty)).sum::<usize>()
+    }
+
+    /// Look up the definition of `opcode`
+    pub fn get(opcode: u8) -> Option<&'static InstructionDef> {
+        static BY_OPCODE: OnceLock<[Option<&'static InstructionDef>; 256]> = OnceLock::new();
+
+        BY_OPCODE.get_or_init(|| {
+            let mut by_opcode = [None; 256];
+            for def in INSTRUCTIONS {
+                by_opcode[def.opcode as usize] = Some(def);
+            }
+            by_opcode
+        })[opcode as usize]
+    }
+
+    /// Look up the definition with `mnemonic` whose operands are laid out like `operands`
+    pub fn find(mnemonic: &str, operands: &[Operand]) -> Option<&'static InstructionDef> {
+        INSTRUCTIONS.iter().find(|def| def.mnemonic == mnemonic && def.fits(operands))
+    }
+
+    /// Whether `operands` are laid out the way this instruction expects
+    pub fn fits(&self, operands: &[Operand]) -> bool {
+        self.operands.iter().copied().eq(operands.iter().map(Operand::kind))
+    }
+}
+
+use OperandKind::*;
+
+/// The whole instruction set
+pub const INSTRUCTIONS: &[InstructionDef] = &[
+    InstructionDef { mnemonic: "nop", opcode: 0, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "move", opcode: 1, operands: &[RegisterPair], cycles: 1 },
+    InstructionDef { mnemonic: "move", opcode: 2, operands: &[Type, Register, Value], cycles: 1 },
+    InstructionDef { mnemonic: "read", opcode: 3, operands: &[Type, Register, Address], cycles: 3 },
+    InstructionDef { mnemonic: "read", opcode: 4, operands: &[Type, RegisterPair], cycles: 3 },
+    InstructionDef { mnemonic: "write", opcode: 5, operands: &[Type, Register, Address], cycles: 3 },
+    InstructionDef { mnemonic: "write", opcode: 6, operands: &[Type, RegisterPair], cycles: 3 },
+    InstructionDef { mnemonic: "push", opcode: 7, operands: &[Type, Register], cycles: 3 },
+    InstructionDef { mnemonic: "push", opcode: 8, operands: &[Type, Value], cycles: 3 },
+    InstructionDef { mnemonic: "pop", opcode: 9, operands: &[Type, Register], cycles: 3 },
+    InstructionDef { mnemonic: "jump", opcode: 10, operands: &[Address], cycles: 2 },
+    InstructionDef { mnemonic: "jump", opcode: 11, operands: &[Register], cycles: 2 },
+    InstructionDef { mnemonic: "jump", opcode: 12, operands: &[Condition, Address], cycles: 2 },
+    InstructionDef { mnemonic: "jump", opcode: 13, operands: &[Condition, Register], cycles: 2 },
+    InstructionDef { mnemonic: "add", opcode: 14, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "sub", opcode: 15, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "mul", opcode: 16, operands: &[], cycles: 3 },
+    InstructionDef { mnemonic: "div", opcode: 17, operands: &[], cycles: 10 },
+    InstructionDef { mnemonic: "equal", opcode: 18, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "less", opcode: 19, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "not", opcode: 20, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "and", opcode: 21, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "or", opcode: 22, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "xor", opcode: 23, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "halt", opcode: 24, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "call", opcode: 25, operands: &[Address], cycles: 4 },
+    InstructionDef { mnemonic: "call", opcode: 26, operands: &[Register], cycles: 4 },
+    InstructionDef { mnemonic: "ret", opcode: 27, operands: &[], cycles: 4 },
+    InstructionDef { mnemonic: "not", opcode: 28, operands: &[RegisterPair], cycles: 1 },
+    InstructionDef { mnemonic: "jump", opcode: 29, operands: &[FlagCondition, Address], cycles: 2 },
+    InstructionDef { mnemonic: "jump", opcode: 30, operands: &[FlagCondition, Register], cycles: 2 },
+    InstructionDef { mnemonic: "neg", opcode: 31, operands: &[RegisterPair], cycles: 1 },
+    // <op> <dst> <src1> <src2>
+    InstructionDef { mnemonic: "add", opcode: 32, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "sub", opcode: 33, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "mul", opcode: 34, operands: &[RegisterPair, Register], cycles: 3 },
+    InstructionDef { mnemonic: "div", opcode: 35, operands: &[RegisterPair, Register], cycles: 10 },
+    InstructionDef { mnemonic: "equal", opcode: 36, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "less", opcode: 37, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "and", opcode: 38, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "or", opcode: 39, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "xor", opcode: 40, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "less_signed", opcode: 41, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "greater", opcode: 42, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "greater_signed", opcode: 43, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "div_signed", opcode: 44, operands: &[RegisterPair, Register], cycles: 10 },
+    InstructionDef { mnemonic: "rem", opcode: 45, operands: &[RegisterPair, Register], cycles: 10 },
+    InstructionDef { mnemonic: "rem_signed", opcode: 46, operands: &[RegisterPair, Register], cycles: 10 },
+    InstructionDef { mnemonic: "shl", opcode: 47, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "shr", opcode: 48, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "sar", opcode: 49, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "rol", opcode: 50, operands: &[RegisterPair, Register], cycles: 1 },
+    InstructionDef { mnemonic: "ror", opcode: 51, operands: &[RegisterPair, Register], cycles: 1 },
+    // <op> <dst> <src> <value>
+    InstructionDef { mnemonic: "add", opcode: 64, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "sub", opcode: 65, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "mul", opcode: 66, operands: &[Type, RegisterPair, Value], cycles: 3 },
+    InstructionDef { mnemonic: "div", opcode: 67, operands: &[Type, RegisterPair, Value], cycles: 10 },
+    InstructionDef { mnemonic: "equal", opcode: 68, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "less", opcode: 69, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "and", opcode: 70, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "or", opcode: 71, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "xor", opcode: 72, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "less_signed", opcode: 73, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "greater", opcode: 74, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "greater_signed", opcode: 75, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "div_signed", opcode: 76, operands: &[Type, RegisterPair, Value], cycles: 10 },
+    InstructionDef { mnemonic: "rem", opcode: 77, operands: &[Type, RegisterPair, Value], cycles: 10 },
+    InstructionDef { mnemonic: "rem_signed", opcode: 78, operands: &[Type, RegisterPair, Value], cycles: 10 },
+    InstructionDef { mnemonic: "shl", opcode: 79, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "shr", opcode: 80, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "sar", opcode: 81, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "rol", opcode: 82, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    InstructionDef { mnemonic: "ror", opcode: 83, operands: &[Type, RegisterPair, Value], cycles: 1 },
+    // read <signed type> <register> <address/register>
+    InstructionDef { mnemonic: "read", opcode: 96, operands: &[SignedType, Register, Address], cycles: 3 },
+    InstructionDef { mnemonic: "read", opcode: 97, operands: &[SignedType, RegisterPair], cycles: 3 },
+    InstructionDef { mnemonic: "syscall", opcode: 98, operands: &[Byte], cycles: 10 },
+    InstructionDef { mnemonic: "iret", opcode: 99, operands: &[], cycles: 4 },
+    InstructionDef { mnemonic: "enable_interrupts", opcode: 100, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "disable_interrupts", opcode: 101, operands: &[], cycles: 1 },
+    InstructionDef { mnemonic: "vectors", opcode: 102, operands: &[Address], cycles: 1 },
+    InstructionDef { mnemonic: "vectors", opcode: 103, operands: &[Register], cycles: 1 }
+];
+
+/// One decoded instruction, registers are indices into `REGISTER_NAMES`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    MoveRegister { dst: u8, src: u8 },
+    MoveValue { ty: DataType, register: u8, value: u64 },
+    ReadAddress { ty: DataType, register: u8, address: u64 },
+    ReadRegister { ty: DataType, register: u8, address_register: u8 },
+    WriteAddress { ty: DataType, register: u8, address: u64 },
+    WriteRegister { ty: DataType, register: u8, address_register: u8 },
+    PushRegister { ty: DataType, register: u8 },
+    PushValue { ty: DataType, value: u64 },
+    Pop { ty: DataType, register: u8 },
+    Jump { address: u64 },
+    JumpRegister { register: u8 },
+    JumpIf { condition: bool, address: u64 },
+    JumpRegisterIf { condition: bool, register: u8 },
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equal,
+    Less,
+    Not,
+    And,
+    Or,
+    Xor,
+    Halt,
+    /// push the return address as an obyte and jump
+    Call { address: u64 },
+    CallRegister { register: u8 },
+    /// pop an obyte return address and jump to it
+    Ret,
+    NotRegister { dst: u8, src: u8 },
+    /// jump if `condition` holds for the flags
+    JumpFlags { condition: FlagCondition, address: u64 },
+    JumpRegisterFlags { condition: FlagCondition, register: u8 },
+    /// two's complement negation
+    Neg { dst: u8, src: u8 },
+    AluRegister { op: AluOp, dst: u8, src1: u8, src2: u8 },
+    /// `value` is zero extended from `ty`
+    AluValue { op: AluOp, ty: DataType, dst: u8, src: u8, value: u64 },
+    /// like `ReadAddress` but the value is sign extended
+    ReadSignedAddress { ty: DataType, register: u8, address: u64 },
+    ReadSignedRegister { ty: DataType, register: u8, address_register: u8 },
+    /// hand control to the host's handler for `number`
+    Syscall { number: u8 },
+    /// pop the flags and program counter an interrupt pushed and turn interrupts back on
+    Iret,
+    EnableInterrupts,
+    DisableInterrupts,
+    /// set where the interrupt vector table starts
+    Vectors { address: u64 },
+    VectorsRegister { register: u8 }
+}
+
+impl Instruction {
+    /// The mnemonic and operands in encoded order, together they pick out the instruction's definition
+    fn form(&self) -> (&'static str, Vec<Operand>) {
+        use Operand::*;
+
+        match *self {
+            Instruction::Nop => ("nop", vec![]),
+            Instruction::MoveRegister { dst, src } => ("move", vec![RegisterPair(dst, src)]),
+            Instruction::MoveValue { ty, register, value } => ("move", vec![Type(ty), Register(register), Value(value)]),
+            Instruction::ReadAddress { ty, register, address } => ("read", vec![Type(ty), Register(register), Address(address)]),
+            Instruction::ReadRegister { ty, register, address_register } => ("read", vec![Type(ty), RegisterPair(register, address_register)]),
+            Instruction::WriteAddress { ty, register, address } => ("write", vec![Type(ty), Register(register), Address(address)]),
+            Instruction::WriteRegister { ty, register, address_register } => ("write", vec![Type(ty), RegisterPair(register, address_register)]),
+            Instruction::PushRegister { ty, register } => ("push", vec![Type(ty), Register(register)]),
+            Instruction::PushValue { ty, value } => ("push", vec![Type(ty), Value(value)]),
+            Instruction::Pop { ty, register } => ("pop", vec![Type(ty), Register(register)]),
+            Instruction::Jump { address } => ("jump", vec![Address(address)]),
+            Instruction::JumpRegister { register } => ("jump", vec![Register(register)]),
+            Instruction::JumpIf { condition, address } => ("jump", vec![Condition(condition), Address(address)]),
+            Instruction::JumpRegisterIf { condition, register } => ("jump", vec![Condition(condition), Register(register)]),
+            Instruction::Add => ("add", vec![]),
+            Instruction::Sub => ("sub", vec![]),
+            Instruction::Mul => ("mul", vec![]),
+            Instruction::Div => ("div", vec![]),
+            Instruction::Equal => ("equal", vec![]),
+            Instruction::Less => ("less", vec![]),
+            Instruction::Not => ("not", vec![]),
+            Instruction::And => ("and", vec![]),
+            Instruction::Or => ("or", vec![]),
+            Instruction::Xor => ("xor", vec![]),
+            Instruction::Halt => ("halt", vec![]),
+            Instruction::Call { address } => ("call", vec![Address(address)]),
+            Instruction::CallRegister { register } => ("call", vec![Register(register)]),
+            Instruction::Ret => ("ret", vec![]),
+            Instruction::NotRegister { dst, src } => ("not", vec![RegisterPair(dst, src)]),
+            Instruction::JumpFlags { condition, address } => ("jump", vec![FlagCondition(condition), Address(address)]),
+            Instruction::JumpRegisterFlags { condition, register } => ("jump", vec![FlagCondition(condition), Register(register)]),
+            Instruction::Neg { dst, src } => ("neg", vec![RegisterPair(dst, src)]),
+            Instruction::ReadSignedAddress { ty, register, address } => ("read", vec![SignedType(ty), Register(register), Address(address)]),
+            Instruction::ReadSignedRegister { ty, register, address_register } => ("read", vec![SignedType(ty), RegisterPair(register, address_register)]),
+            Instruction::Syscall { number } => ("syscall", vec![Byte(number)]),
+            Instruction::Iret => ("iret", vec![]),
+            Instruction::EnableInterrupts => ("enable_interrupts", vec![]),
+            Instruction::DisableInterrupts => ("disable_interrupts", vec![]),
+            Instruction::Vectors { address } => ("vectors", vec![Address(address)]),
+            Instruction::VectorsRegister { register } => ("vectors", vec![Register(register)]),
+            Instruction::AluRegister { op, dst, src1, src2 } => (op.name(), vec![RegisterPair(dst, src1), Register(src2)]),
+            Instruction::AluValue { op, ty, dst, src, value } => (op.name(), vec![Type(ty), RegisterPair(dst, src), Value(value)])
+        }
+    }
+
+    /// The operands in encoded order
+    pub fn operands(&self) -> Vec<Operand> {
+        self.form().1
+    }
+
+    /// Build an instruction from an opcode and its operands in encoded order
+    ///
+    /// `None` if the operands don't fit the opcode
+    pub fn from_operands(opcode: u8, operands: &[Operand]) -> Option<Instruction> {
+        use Operand::*;
+
+        let def = InstructionDef::get(opcode)?;
+        if !def.fits(operands) {
+            return None;
+        }
+
+        let instruction = match (def.mnemonic, operands) {
+            ("nop", []) => Instruction::Nop,
+            ("move", [RegisterPair(dst, src)]) => Instruction::MoveRegister { dst: *dst, src: *src },
+            ("move", [Type(ty), Register(register), Value(value)]) => Instruction::MoveValue { ty: *ty, register: *register, value: *value },
+            ("read", [Type(ty), Register(register), Address(address)]) => Instruction::ReadAddress { ty: *ty, register: *register, address: *address },
+            ("read", [Type(ty), RegisterPair(register, address_register)]) => Instruction::ReadRegister { ty: *ty, register: *register, address_register: *address_register },
+            ("write", [Type(ty), Register(register), Address(address)]) => Instruction::WriteAddress { ty: *ty, register: *register, address: *address },
+            ("write", [Type(ty), RegisterPair(register, address_register)]) => Instruction::WriteRegister { ty: *ty, register: *register, address_register: *address_register },
+            ("push", [Type(ty), Register(register)]) => Instruction::PushRegister { ty: *ty, register: *register },
+            ("push", [Type(ty), Value(value)]) => Instruction::PushValue { ty: *ty, value: *value },
+            ("pop", [Type(ty), Register(register)]) => Instruction::Pop { ty: *ty, register: *register },
+            ("jump", [Address(address)]) => Instruction::Jump { address: *address },
+            ("jump", [Register(register)]) => Instruction::JumpRegister { register: *register },
+            ("jump", [Condition(condition), Address(address)]) => Instruction::JumpIf { condition: *condition, address: *address },
+            ("jump", [Condition(condition), Register(register)]) => Instruction::JumpRegisterIf { condition: *condition, register: *register },
+            ("add", []) => Instruction::Add,
+            ("sub", []) => Instruction::Sub,
+            ("mul", []) => Instruction::Mul,
+            ("div", []) => Instruction::Div,
+            ("equal", []) => Instruction::Equal,
+            ("less", []) => Instruction::Less,
+            ("not", []) => Instruction::Not,
+            ("and", []) => Instruction::And,
+            ("or", []) => Instruction::Or,
+            ("xor", []) => Instruction::Xor,
+            ("halt", []) => Instruction::Halt,
+            ("call", [Address(address)]) => Instruction::Call { address: *address },
+            ("call", [Register(register)]) => Instruction::CallRegister { register: *register },
+            ("ret", []) => Instruction::Ret,
+            ("not", [RegisterPair(dst, src)]) => Instruction::NotRegister { dst: *dst, src: *src },
+            ("jump", [FlagCondition(condition), Address(address)]) => Instruction::JumpFlags { condition: *condition, address: *address },
+            ("jump", [FlagCondition(condition), Register(register)]) => Instruction::JumpRegisterFlags { condition: *condition, register: *register },
+            ("neg", [RegisterPair(dst, src)]) => Instruction::Neg { dst: *dst, src: *src },
+            ("read", [SignedType(ty), Register(register), Address(address)]) => Instruction::ReadSignedAddress { ty: *ty, register: *register, address: *address },
+            ("read", [SignedType(ty), RegisterPair(register, address_register)]) => Instruction::ReadSignedRegister { ty: *ty, register: *register, address_register: *address_register },
+            ("syscall", [Byte(number)]) => Instruction::Syscall { number: *number },
+            ("iret", []) => Instruction::Iret,
+            ("enable_interrupts", []) => Instruction::EnableInterrupts,
+            ("disable_interrupts", []) => Instruction::DisableInterrupts,
+            ("vectors", [Address(address)]) => Instruction::Vectors { address: *address },
+            ("vectors", [Register(register)]) => Instruction::VectorsRegister { register: *register },
+            (mnemonic, [RegisterPair(dst, src1), Register(src2)]) => Instruction::AluRegister {
+                op: AluOp::from_name(mnemonic)?,
+                dst: *dst,
+                src1: *src1,
+                src2: *src2
+            },
+            (mnemonic, [Type(ty), RegisterPair(dst, src), Value(value)]) => Instruction::AluValue {
+                op: AluOp::from_name(mnemonic)?,
+                ty: *ty,
+                dst: *dst,
+                src: *src,
+                value: *value
+            },
+            _ => {
+                return None;
+            }
+        };
+
+        Some(instruction)
+    }
+
+    pub fn opcode(&self) -> u8 {
+        self.def().opcode
+    }
+
+    pub fn def(&self) -> &'static InstructionDef {
+        let (mnemonic, operands) = self.form();
+        InstructionDef::find(mnemonic, &operands).expect("every instruction has a definition")
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.form().0
+    }
+
+    /// The `<type>` operand, if the instruction has one
+    pub fn data_type(&self) -> Option<DataType> {
+        self.operands().iter().find_map(|operand| match operand {
+            Operand::Type(ty) | Operand::SignedType(ty) => Some(*ty),
+            _ => {None}
+        })
+    }
+
+    /// Encoded size in bytes
+    pub fn encoded_len(&self) -> usize {
+        self.def().encoded_len(self.data_type())
+    }
+
+    /// Where the `Address` operand sits inside the encoded instruction, this is what the
+    /// assembler patches when the address is a label
+    pub fn address_offset(&self) -> Option<usize> {
+        let def = self.def();
+        let ty = self.data_type();
+        let index = def.operands.iter().position(|operand| *operand == OperandKind::Address)?;
+
+        Some(1 + def.operands[..index].iter().map(|operand| operand.size(ty)).sum::<usize>())
+    }
+
+    /// Address this instruction may jump to, if it is known without running the program
+    pub fn jump_target(&self) -> Option<u64> {
+        match self {
+            Instruction::Jump { address } |
+            Instruction::JumpIf { address, .. } |
+            Instruction::JumpFlags { address, .. } |
+            Instruction::Call { address } => Some(*address),
+            _ => {None}
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (mnemonic, operands) = self.form();
+        let def = InstructionDef::find(mnemonic, &operands).expect("every instruction has a definition");
+        let ty = self.data_type();
+
+        let mut bytes = vec![def.opcode];
+        for operand in operands {
+            match operand {
+                Operand::Type(ty) | Operand::SignedType(ty) => bytes.push(ty.index()),
+                Operand::Register(register) => bytes.push(register),
+                Operand::RegisterPair(high, low) => bytes.push((high << 4) | low),
+                Operand::Value(value) => {
+                    let size = OperandKind::Value.size(ty);
+                    bytes.extend_from_slice(&value.to_be_bytes()[8 - size..]);
+                }
+                Operand::Address(address) => bytes.extend_from_slice(&address.to_be_bytes()),
+                Operand::Condition(condition) => bytes.push(condition as u8),
+                Operand::FlagCondition(condition) => bytes.push(condition.index()),
+                Operand::Byte(byte) => bytes.push(byte)
+            }
+        }
+
+        bytes
+    }
+
+    /// Write the instruction in assembler syntax, naming jump targets with `label`
+    pub fn fmt_with_labels(&self, f: &mut dyn fmt::Write, label: &dyn Fn(u64) -> Option<String>) -> fmt::Result {
+        let reg = |register: &u8| REGISTER_NAMES[*register as usize];
+        let target = |address: &u64| label(*address).unwrap_or_else(|| address.to_string());
+        let mnemonic = self.mnemonic();
+
+        match self {
+            Instruction::MoveRegister { dst, src } |
+            Instruction::NotRegister { dst, src } |
+            Instruction::Neg { dst, src } => write!(f, "{} {} {}", mnemonic, reg(dst), reg(src)),
+            Instruction::MoveValue { ty, register, value } => write!(f, "{} {} {} {}", mnemonic, ty.name(), reg(register), value),
+            Instruction::ReadAddress { ty, register, address } |
+            Instruction::WriteAddress { ty, register, address } => write!(f, "{} {} {} {}", mnemonic, ty.name(), reg(register), address),
+            Instruction::ReadRegister { ty, register, address_register } |
+            Instruction::WriteRegister { ty, register, address_register } => write!(f, "{} {} {} {}", mnemonic, ty.name(), reg(register), reg(address_register)),
+            Instruction::PushRegister { ty, register } |
+            Instruction::Pop { ty, register } => write!(f, "{} {} {}", mnemonic, ty.name(), reg(register)),
+            Instruction::ReadSignedAddress { ty, register, address } => write!(f, "{} {} {} {}", mnemonic, ty.signed_name(), reg(register), address),
+            Instruction::ReadSignedRegister { ty, register, address_register } => {
+                write!(f, "{} {} {} {}", mnemonic, ty.signed_name(), reg(register), reg(address_register))
+            }
+            Instruction::Syscall { number } => write!(f, "{} {}", mnemonic, number),
+            Instruction::PushValue { ty, value } => write!(f, "{} {} {}", mnemonic, ty.name(), value),
+            Instruction::Jump { address } |
+            Instruction::Call { address } |
+            Instruction::Vectors { address } => write!(f, "{} {}", mnemonic, target(address)),
+            Instruction::JumpRegister { register } |
+            Instruction::CallRegister { register } |
+            Instruction::VectorsRegister { register } => write!(f, "{} {}", mnemonic, reg(register)),
+            Instruction::JumpIf { condition, address } => write!(f, "{} {} {}", mnemonic, target(address), condition),
+            Instruction::JumpRegisterIf { condition, register } => write!(f, "{} {} {}", mnemonic, reg(register), condition),
+            // flag jumps are written in their short form
+            Instruction::JumpFlags { condition, address } => write!(f, "j{} {}", condition.name(), target(address)),
+            Instruction::JumpRegisterFlags { condition, register } => write!(f, "j{} {}", condition.name(), reg(register)),
+            Instruction::AluRegister { dst, src1, src2, .. } => write!(f, "{} {} {} {}", mnemonic, reg(dst), reg(src1), reg(src2)),
+            // the type is only written out when the assembler wouldn't pick it on its own
+            Instruction::AluValue { ty, dst, src, value, .. } if *ty == DataType::smallest_for(*value) => {
+                write!(f, "{} {} {} {}", mnemonic, reg(dst), reg(src), value)
+            }
+            Instruction::AluValue { ty, dst, src, value, .. } => write!(f, "{} {} {} {} {}", mnemonic, ty.name(), reg(dst), reg(src), value),
+            _ => write!(f, "{}", mnemonic)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with_labels(f, &|_| None)
+    }
+}
+
+/// Why some bytes couldn't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// the bytes ran out in the middle of an instruction
+    Truncated,
+    BadOpcode(u8),
+    BadOperandType(u8),
+    BadRegister(u8),
+    /// a condition byte that isn't 0 (false) or 1 (true), or isn't a `FlagCondition`
+    BadCondition(u8)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "instruction is cut off"),
+            DecodeError::BadOpcode(opcode) => write!(f, "bad opcode {}", opcode),
+            DecodeError::BadOperandType(specified_type) => write!(f, "bad operand type {}", specified_type),
+            DecodeError::BadRegister(register) => write!(f, "bad register {}", register),
+            DecodeError::BadCondition(condition) => write!(f, "bad condition {}", condition)
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decode the instruction at the start of `bytes`
+///
+/// returns the instruction and how many bytes it takes up
+pub fn decode(bytes: &[u8]) -> Result<(Instruction, usize), DecodeError> {
+    let opcode = *bytes.first().ok_or(DecodeError::Truncated)?;
+    let def = InstructionDef::get(opcode).ok_or(DecodeError::BadOpcode(opcode))?;
+
+    let mut offset = 1;
+    let mut next = |size: usize| -> Result<u64, DecodeError> {
+        let value_bytes = bytes.get(offset..offset + size).ok_or(DecodeError::Truncated)?;
+        offset += size;
+
+        Ok(value_bytes.iter().fold(0, |value, byte| (value << 8) | *byte as u64))
+    };
+
+    let mut operands = [Operand::Value(0); 3];
+    let mut ty = None;
+
+    for (i, kind) in def.operands.iter().enumerate() {
+        operands[i] = match kind {
+            OperandKind::Type | OperandKind::SignedType => {
+                let index = next(1)? as u8;
+                let data_type = DataType::from_index(index).ok_or(DecodeError::BadOperandType(index))?;
+                ty = Some(data_type);
+
+                match kind {
+                    OperandKind::SignedType => Operand::SignedType(data_type),
+                    _ => Operand::Type(data_type)
+                }
+            }
+            OperandKind::Register => {
+                let register = next(1)? as u8;
+                if register >= 16 {
+                    return Err(DecodeError::BadRegister(register));
+                }
+                Operand::Register(register)
+            }
+            OperandKind::RegisterPair => {
+                let registers = next(1)? as u8;
+                Operand::RegisterPair(registers >> 4, registers & 0b0000_1111)
+            }
+            OperandKind::Value => Operand::Value(next(kind.size(ty))?),
+            OperandKind::Address => Operand::Address(next(8)?),
+            OperandKind::Condition => match next(1)? as u8 {
+                0 => Operand::Condition(false),
+                1 => Operand::Condition(true),
+                condition => {
+                    return Err(DecodeError::BadCondition(condition));
+                }
+            },
+            OperandKind::FlagCondition => {
+                let index = next(1)? as u8;
+                Operand::FlagCondition(FlagCondition::from_index(index).ok_or(DecodeError::BadCondition(index))?)
+            }
+            OperandKind::Byte => Operand::Byte(next(1)? as u8)
+        };
+    }
+
+    let instruction = Instruction::from_operands(opcode, &operands[..def.operands.len()])
+        .expect("isa table and Instruction::from_operands disagree");
+
+    Ok((instruction, offset))
+}
@@ -0,0 +1,10 @@
+pub mod assembler;
+pub mod bus;
+pub mod devices;
+pub mod disassembler;
+pub mod emulator;
+pub mod isa;
+pub mod protection;
+pub mod ram;
+pub mod syscalls;
+pub mod trace;
@@ -1,11 +1,248 @@
-pub mod emulator;
+use std::{cell::RefCell, io::BufWriter, rc::Rc};
+
+use c64::{
+    devices::{
+        console::{Console, Input, CONSOLE_BASE, CONSOLE_LEN},
+        disk::{Disk, DISK_BASE, DISK_LEN},
+        framebuffer::{Framebuffer, FRAMEBUFFER_BASE, FRAMEBUFFER_LEN},
+        random::{Random, RANDOM_BASE, RANDOM_LEN},
+        rtc::{Rtc, RTC_BASE, RTC_LEN},
+        timer::{Timer, TIMER_BASE, TIMER_IRQ, TIMER_LEN},
+        uart::{Uart, UART_BASE, UART_IRQ, UART_LEN},
+        IO_BASE
+    },
+    emulator::{self, Budget, RunOutcome, RAM_SIZE},
+    protection::Permissions,
+    syscalls,
+    trace::{TraceFormat, TraceWriter}
+};
+
+/// Parse `<start>..<end>` into a range of addresses
+fn parse_range(range: &str) -> Option<std::ops::Range<u64>> {
+    let (start, end) = range.split_once("..")?;
+
+    Some(start.parse().ok()?..end.parse().ok()?)
+}
+
+/// Open the uart `--uart` asked for, `unix:<path>`, `pipe:<input>,<output>` or `pty`
+#[cfg(unix)]
+fn open_uart(uart: &str) -> std::io::Result<Uart> {
+    if let Some(path) = uart.strip_prefix("unix:") {
+        return Uart::unix_socket(path);
+    }
+
+    if let Some(paths) = uart.strip_prefix("pipe:") {
+        let (input, output) = paths.split_once(',').expect("--uart pipe: needs <input>,<output>");
+        return Uart::pipes(input, output);
+    }
+
+    if uart == "pty" {
+        let (uart, path) = Uart::pty()?;
+        eprintln!("uart is on {}", path.display());
+        return Ok(uart);
+    }
+
+    panic!("--uart needs unix:<path>, pipe:<input>,<output> or pty");
+}
+
+#[cfg(not(unix))]
+fn open_uart(_uart: &str) -> std::io::Result<Uart> {
+    panic!("--uart is only supported on unix");
+}
+
+/// Parse `<start>..<end>:<permissions>` into a range and what it allows
+fn parse_region(region: &str) -> Option<(std::ops::Range<u64>, Permissions)> {
+    let (range, permissions) = region.rsplit_once(':')?;
+
+    Some((parse_range(range)?, Permissions::from_name(permissions)?))
+}
 
 fn main() {
-    let mut args = std::env::args();
-    let bin_filename = args.nth(1).unwrap();
-    let bin = std::fs::read(bin_filename).unwrap();
+    let mut args = std::env::args().skip(1);
+    let mut bin_filename = None;
+    let mut budget = Budget::Unlimited;
+    let mut trace = false;
+    let mut trace_file = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_range = None;
+    let mut screenshot = None;
+    let mut disk = None;
+    let mut uart = None;
+    let mut seed = None;
+    let mut time = None;
+    let mut regions = Vec::new();
+    let mut protect_program = false;
+    let mut stack_size = None;
+    let mut ram_size = RAM_SIZE as u64;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-steps" => {
+                let max_steps = args.next().expect("--max-steps needs a number");
+                let max_steps = max_steps.parse::<u64>().expect("--max-steps needs a number");
+                budget = Budget::Steps(max_steps);
+            }
+            "--max-cycles" => {
+                let max_cycles = args.next().expect("--max-cycles needs a number");
+                let max_cycles = max_cycles.parse::<u64>().expect("--max-cycles needs a number");
+                budget = Budget::Cycles(max_cycles);
+            }
+            "--trace" => {
+                trace = true;
+            }
+            "--trace-file" => {
+                trace = true;
+                trace_file = Some(args.next().expect("--trace-file needs a filepath"));
+            }
+            "--trace-format" => {
+                trace = true;
+                trace_format = match args.next().as_deref() {
+                    Some("text") => TraceFormat::Text,
+                    Some("json") => TraceFormat::Json,
+                    _ => panic!("--trace-format needs to be text or json")
+                };
+            }
+            "--trace-range" => {
+                trace = true;
+                let range = args.next().expect("--trace-range needs <start>..<end>");
+                trace_range = Some(parse_range(&range).expect("--trace-range needs <start>..<end>"));
+            }
+            "--screenshot" => {
+                screenshot = Some(args.next().expect("--screenshot needs a filepath"));
+            }
+            "--disk" => {
+                disk = Some(args.next().expect("--disk needs a filepath"));
+            }
+            "--uart" => {
+                uart = Some(args.next().expect("--uart needs unix:<path>, pipe:<input>,<output> or pty"));
+            }
+            "--seed" => {
+                let number = args.next().expect("--seed needs a number");
+                seed = Some(number.parse::<u64>().expect("--seed needs a number"));
+            }
+            "--time" => {
+                let seconds = args.next().expect("--time needs a number of seconds");
+                time = Some(seconds.parse::<u64>().expect("--time needs a number of seconds"));
+            }
+            "--protect-program" => {
+                protect_program = true;
+            }
+            "--protect" => {
+                let region = args.next().expect("--protect needs <start>..<end>:<rwx>");
+                regions.push(parse_region(&region).expect("--protect needs <start>..<end>:<rwx>"));
+            }
+            "--ram-size" => {
+                let size = args.next().expect("--ram-size needs a number of bytes");
+                ram_size = size.parse::<u64>().expect("--ram-size needs a number of bytes");
+            }
+            "--stack-size" => {
+                let size = args.next().expect("--stack-size needs a number of bytes");
+                stack_size = Some(size.parse::<u64>().expect("--stack-size needs a number of bytes"));
+            }
+            _ => {
+                bin_filename = Some(arg);
+            }
+        }
+    }
+
+    let bin_filename = bin_filename.expect("no binary file given");
+    let bin = std::fs::read(&bin_filename).unwrap();
+
+    // the devices are mapped right above where ram is allowed to end
+    assert!(ram_size <= IO_BASE, "--ram-size can't be more than {} bytes, the devices start there", IO_BASE);
+    assert!(bin.len() as u64 <= ram_size, "{} doesn't fit in {} bytes of ram", bin_filename, ram_size);
+
+    let mut emulator = emulator::Emulator::with_ram_size(&[], ram_size);
+    if protect_program {
+        emulator.load_at_with(0, &bin, Permissions::READ | Permissions::EXECUTE).unwrap();
+    } else {
+        emulator.load_at(0, &bin).unwrap();
+    }
+    if let Some(stack_size) = stack_size {
+        assert!(stack_size <= ram_size, "--stack-size can't be more than the {} bytes of ram", ram_size);
+        emulator.set_stack(ram_size - stack_size..ram_size);
+    }
+    for (range, permissions) in regions {
+        emulator.protect(range, permissions);
+    }
+
+    // the console and the READ_LINE syscall take turns on the same stdin
+    let input = Input::stdin();
+    emulator.bus_mut().map(CONSOLE_BASE, CONSOLE_LEN, Box::new(Console::with_input(std::io::stdout(), input.clone())));
+    emulator.bus_mut().map_with_irq(TIMER_BASE, TIMER_LEN, TIMER_IRQ, Box::new(Timer::new()));
+
+    let rtc = match time {
+        Some(seconds) => Rtc::frozen(std::time::Duration::from_secs(seconds)),
+        None => Rtc::new()
+    };
+    emulator.bus_mut().map(RTC_BASE, RTC_LEN, Box::new(rtc));
+
+    let random = match seed {
+        Some(seed) => Random::new(seed),
+        None => Random::from_time()
+    };
+    emulator.bus_mut().map(RANDOM_BASE, RANDOM_LEN, Box::new(random));
+
+    if let Some(disk) = disk {
+        let image = Disk::open(&disk).unwrap_or_else(|error| panic!("could not open disk image {}: {}", disk, error));
+        emulator.bus_mut().map(DISK_BASE, DISK_LEN, Box::new(image));
+    }
+
+    if let Some(uart) = uart {
+        let device = open_uart(&uart).unwrap_or_else(|error| panic!("could not open uart {}: {}", uart, error));
+        emulator.bus_mut().map_with_irq(UART_BASE, UART_LEN, UART_IRQ, Box::new(device));
+    }
+
+    // snapshots the program asks for are numbered after the screenshot, the screenshot itself is saved when it stops
+    let framebuffer = Rc::new(RefCell::new(match &screenshot {
+        Some(screenshot) => Framebuffer::with_snapshots(screenshot),
+        None => Framebuffer::new()
+    }));
+    emulator.bus_mut().map(FRAMEBUFFER_BASE, FRAMEBUFFER_LEN, Box::new(framebuffer.clone()));
+
+    syscalls::install(&mut emulator, std::io::stdout(), input);
+
+    if trace {
+        match trace_file {
+            Some(trace_file) => {
+                let trace_file = std::fs::File::create(trace_file).unwrap();
+                emulator.set_tracer(Some(Box::new(TraceWriter::new(BufWriter::new(trace_file), trace_format, trace_range))));
+            }
+            None => {
+                emulator.set_tracer(Some(Box::new(TraceWriter::new(std::io::stderr(), trace_format, trace_range))));
+            }
+        }
+    }
+
+    let outcome = emulator.run(budget);
+
+    // dropping the tracer flushes the trace file, process::exit wouldn't
+    emulator.set_tracer(None);
+
+    for (path, error) in framebuffer.borrow().snapshot_errors() {
+        eprintln!("could not save snapshot {}: {}", path.display(), error);
+    }
 
-    let mut emulator = emulator::Emulator::new(&bin);
+    if let Some(screenshot) = screenshot {
+        if let Err(error) = framebuffer.borrow().render().save(&screenshot) {
+            eprintln!("could not save screenshot {}: {}", screenshot, error);
+        }
+    }
 
-    emulator.run();
+    match outcome {
+        // exit codes past 255 get cut down by the os
+        RunOutcome::Halted => std::process::exit(emulator.exit_code() as i32),
+        RunOutcome::StepLimit => {
+            eprintln!("stopped after reaching the step limit");
+            std::process::exit(2);
+        }
+        RunOutcome::CycleLimit => {
+            eprintln!("stopped after reaching the cycle limit");
+            std::process::exit(2);
+        }
+        RunOutcome::Fault(fault) => {
+            eprintln!("fault: {}", fault);
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,152 @@
+use std::{fmt, ops::Range};
+
+/// What the cpu is trying to do with a byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// fetching it as part of an instruction
+    Execute
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute")
+        }
+    }
+}
+
+/// Which accesses a region allows, a combination of `READ`, `WRITE` and `EXECUTE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions(0);
+    pub const READ: Permissions = Permissions(1);
+    pub const WRITE: Permissions = Permissions(2);
+    pub const EXECUTE: Permissions = Permissions(4);
+    pub const ALL: Permissions = Permissions(7);
+
+    /// Parse the `rwx` style used on the command line, any of the three letters in any order, `-` for none
+    pub fn from_name(name: &str) -> Option<Permissions> {
+        let mut permissions = Permissions::NONE;
+
+        match name {
+            "" => return None,
+            "-" => return Some(permissions),
+            _ => {}
+        }
+
+        for letter in name.chars() {
+            let permission = match letter {
+                'r' => Permissions::READ,
+                'w' => Permissions::WRITE,
+                'x' => Permissions::EXECUTE,
+                _ => {
+                    return None;
+                }
+            };
+
+            permissions = permissions | permission;
+        }
+
+        Some(permissions)
+    }
+
+    pub fn allows(self, access: Access) -> bool {
+        let needed = match access {
+            Access::Read => Permissions::READ,
+            Access::Write => Permissions::WRITE,
+            Access::Execute => Permissions::EXECUTE
+        };
+
+        self.0 & needed.0 != 0
+    }
+}
+
+impl std::ops::BitOr for Permissions {
+    type Output = Permissions;
+
+    fn bitor(self, other: Permissions) -> Permissions {
+        Permissions(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Permissions::NONE {
+            return write!(f, "-");
+        }
+
+        for (access, letter) in [(Access::Read, 'r'), (Access::Write, 'w'), (Access::Execute, 'x')] {
+            if self.allows(access) {
+                write!(f, "{}", letter)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A range of addresses and what can be done with them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub range: Range<u64>,
+    pub permissions: Permissions
+}
+
+/// The permissions the cpu checks every access against
+///
+/// a byte gets the permissions of the last region added that covers it, and everything outside
+/// the regions can be read, written and executed, so a program that never sets any runs as before
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Protection {
+    regions: Vec<Region>
+}
+
+impl Protection {
+    pub fn new() -> Protection {
+        Protection::default()
+    }
+
+    /// Give `range` the `permissions`, overriding whatever was set for it before
+    pub fn protect(&mut self, range: Range<u64>, permissions: Permissions) {
+        self.regions.push(Region {
+            range,
+            permissions
+        });
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// What can be done with the byte at `addr`
+    pub fn permissions(&self, addr: u64) -> Permissions {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.range.contains(&addr))
+            .map_or(Permissions::ALL, |region| region.permissions)
+    }
+
+    /// Make sure `access` is allowed on all `len` bytes from `addr`, returns the first byte it isn't allowed on
+    pub fn check(&self, addr: u64, len: usize, access: Access) -> Result<(), u64> {
+        if self.regions.is_empty() {
+            return Ok(());
+        }
+
+        for i in 0..len as u64 {
+            let byte_addr = addr.wrapping_add(i);
+
+            if !self.permissions(byte_addr).allows(access) {
+                return Err(byte_addr);
+            }
+        }
+
+        Ok(())
+    }
+}
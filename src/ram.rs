@@ -0,0 +1,122 @@
+use std::{borrow::Cow, collections::HashMap, ops::Range};
+
+use crate::emulator::FaultKind;
+
+/// Ram is handed out in pages of this many bytes, the first time something nonzero is written to them
+pub const PAGE_SIZE: u64 = 0x1_0000;
+
+/// What a page that was never written to reads as
+static ZERO_PAGE: [u8; PAGE_SIZE as usize] = [0; PAGE_SIZE as usize];
+
+/// Memory from address 0 up to `size`, kept on the heap in pages that are only allocated once they're written to
+///
+/// so a program can have gigabytes of address space and only pay for the parts it actually uses,
+/// pages are looked up by number so even the size itself costs nothing
+pub struct Ram {
+    size: u64,
+    pages: HashMap<u64, Box<[u8]>>
+}
+
+impl Ram {
+    /// `size` bytes of ram that all read as 0
+    pub fn new(size: u64) -> Ram {
+        Ram {
+            size,
+            pages: HashMap::new()
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// How many bytes the pages written to so far take up
+    pub fn allocated(&self) -> u64 {
+        self.pages.len() as u64 * PAGE_SIZE
+    }
+
+    /// Whether every address in `range` is ram
+    pub fn contains(&self, range: &Range<u64>) -> bool {
+        range.start <= range.end && range.end <= self.size
+    }
+
+    /// Make sure all `len` bytes from `addr` are ram
+    fn check(&self, addr: u64, len: usize) -> Result<(), FaultKind> {
+        match addr.checked_add(len as u64) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(FaultKind::BadAddress(addr.max(self.size)))
+        }
+    }
+
+    /// The pieces `len` bytes from `addr` fall into, as the page, the offset into it and the range they cover of the `len` bytes
+    fn pieces(addr: u64, len: usize) -> impl Iterator<Item = (u64, usize, Range<usize>)> {
+        let mut done = 0;
+
+        std::iter::from_fn(move || {
+            if done >= len {
+                return None;
+            }
+
+            let byte_addr = addr + done as u64;
+            let offset = (byte_addr % PAGE_SIZE) as usize;
+            let piece_len = (PAGE_SIZE as usize - offset).min(len - done);
+
+            let piece = (byte_addr / PAGE_SIZE, offset, done..done + piece_len);
+            done += piece_len;
+
+            Some(piece)
+        })
+    }
+
+    /// Fill `buf` with the bytes starting at `addr`
+    pub fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), FaultKind> {
+        self.check(addr, buf.len())?;
+
+        for (page, offset, range) in Ram::pieces(addr, buf.len()) {
+            let page = self.pages.get(&page).map_or(&ZERO_PAGE[..], |page| &page[..]);
+            buf[range.clone()].copy_from_slice(&page[offset..offset + range.len()]);
+        }
+
+        Ok(())
+    }
+
+    /// Write `bytes` starting at `addr`
+    pub fn write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), FaultKind> {
+        self.check(addr, bytes.len())?;
+
+        for (page, offset, range) in Ram::pieces(addr, bytes.len()) {
+            let bytes = &bytes[range];
+
+            // zeros are what an unallocated page already holds
+            if !self.pages.contains_key(&page) && bytes.iter().all(|byte| *byte == 0) {
+                continue;
+            }
+
+            let page = self.pages.entry(page).or_insert_with(|| vec![0; PAGE_SIZE as usize].into_boxed_slice());
+
+            page[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        Ok(())
+    }
+
+    /// The bytes in `range`, borrowed if they're all on one page and copied if not, `None` if any of them isn't ram
+    pub fn slice(&self, range: Range<u64>) -> Option<Cow<'_, [u8]>> {
+        if !self.contains(&range) {
+            return None;
+        }
+
+        let len = (range.end - range.start) as usize;
+        let offset = (range.start % PAGE_SIZE) as usize;
+
+        if offset + len <= PAGE_SIZE as usize {
+            let page = self.pages.get(&(range.start / PAGE_SIZE)).map_or(&ZERO_PAGE[..], |page| &page[..]);
+            return Some(Cow::Borrowed(&page[offset..offset + len]));
+        }
+
+        let mut bytes = vec![0; len];
+        self.read(range.start, &mut bytes).ok()?;
+
+        Some(Cow::Owned(bytes))
+    }
+}
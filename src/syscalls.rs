@@ -0,0 +1,99 @@
+use std::{
+    cell::RefCell,
+    io::Write,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use crate::{
+    bus::Bus,
+    devices::console::Input,
+    emulator::{Emulator, FaultKind}
+};
+
+/// Print a as a signed number
+pub const PRINT_INT: u8 = 0;
+/// Print b bytes starting at the address in a
+pub const PRINT_STRING: u8 = 1;
+/// Read a line into the buffer at the address in a that's b bytes long, a is set to how many bytes
+/// were stored without the newline, or -1 if the input has ended
+pub const READ_LINE: u8 = 2;
+/// Stop the program with a as the exit code
+pub const EXIT: u8 = 3;
+/// Set a to the milliseconds since 1970-01-01 00:00:00 UTC
+pub const TIME: u8 = 4;
+
+/// Most bytes `PRINT_STRING` copies out of memory at a time
+const PRINT_CHUNK: usize = 4096;
+
+/// Set handlers for all of the built in syscalls, printing goes to `output` and reading comes from `input`
+///
+/// `input` can be shared with the console so both read from the same place
+pub fn install<B: Bus, W: Write + 'static>(emulator: &mut Emulator<B>, output: W, input: Input) {
+    let output = Rc::new(RefCell::new(output));
+
+    let print_output = output.clone();
+    emulator.set_syscall(PRINT_INT, Box::new(move |emulator| {
+        let _ = write!(print_output.borrow_mut(), "{}", emulator.register(0) as i64);
+        Ok(())
+    }));
+
+    let print_output = output.clone();
+    emulator.set_syscall(PRINT_STRING, Box::new(move |emulator| {
+        let start = emulator.register(0);
+        let end = start.checked_add(emulator.register(1)).ok_or(FaultKind::BadAddress(start))?;
+
+        // the length comes from the program, so it's copied a piece at a time instead of all at once,
+        // whatever came before a bad address has already been printed when the fault is raised
+        let mut chunk = [0; PRINT_CHUNK];
+        let mut addr = start;
+        while addr < end {
+            let len = (end - addr).min(PRINT_CHUNK as u64) as usize;
+            emulator.read_bytes(addr, &mut chunk[..len])?;
+
+            let _ = print_output.borrow_mut().write_all(&chunk[..len]);
+            addr += len as u64;
+        }
+
+        Ok(())
+    }));
+
+    emulator.set_syscall(READ_LINE, Box::new(move |emulator| {
+        // whatever was printed should be on screen before waiting for the line
+        let _ = output.borrow_mut().flush();
+
+        let mut line = Vec::new();
+        let mut ended = true;
+        while let Some(byte) = input.read_byte() {
+            ended = false;
+            if byte == b'\n' {
+                break;
+            }
+            line.push(byte);
+        }
+
+        if ended {
+            emulator.set_register(0, u64::MAX);
+            return Ok(());
+        }
+
+        // anything that doesn't fit in the buffer is dropped
+        line.truncate(emulator.register(1) as usize);
+
+        emulator.write_bytes(emulator.register(0), &line)?;
+        emulator.set_register(0, line.len() as u64);
+        Ok(())
+    }));
+
+    emulator.set_syscall(EXIT, Box::new(|emulator| {
+        emulator.exit(emulator.register(0));
+        Ok(())
+    }));
+
+    emulator.set_syscall(TIME, Box::new(|emulator| {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        emulator.set_register(0, since_epoch.as_millis() as u64);
+        Ok(())
+    }));
+}
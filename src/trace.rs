@@ -0,0 +1,163 @@
+use std::{io::Write, ops::Range};
+
+use crate::{
+    emulator::{Fault, COUNTER_REG},
+    isa::{decode, Instruction, REGISTER_NAMES}
+};
+
+/// A register that changed during a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub register: usize,
+    pub old: u64,
+    pub new: u64
+}
+
+/// Bytes the program wrote to memory during a step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u64,
+    pub bytes: Vec<u8>
+}
+
+/// Everything that happened during one `Emulator::step`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// address of the instruction
+    pub pc: u64,
+    /// raw bytes of the instruction, cut short if it faulted while being fetched
+    pub bytes: Vec<u8>,
+    /// registers that changed, the program counter is only listed when it didn't just move past the instruction
+    pub registers: Vec<RegisterChange>,
+    pub memory: Vec<MemoryWrite>,
+    pub fault: Option<Fault>
+}
+
+impl TraceEvent {
+    /// The instruction that ran, `None` if its bytes don't decode
+    pub fn instruction(&self) -> Option<Instruction> {
+        decode(&self.bytes).ok().map(|(instruction, _)| instruction)
+    }
+
+    /// The instruction in assembler syntax, `"?"` if it doesn't decode
+    pub fn instruction_text(&self) -> String {
+        match self.instruction() {
+            Some(instruction) => instruction.to_string(),
+            None => "?".to_string()
+        }
+    }
+}
+
+/// Something that gets told about every step the emulator takes
+pub trait TraceSink {
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// one human readable line per step
+    Text,
+    /// one json object per line
+    Json
+}
+
+/// Writes trace events line by line to anything that implements `Write`
+///
+/// `range` - only steps whose pc falls in this range get written
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    range: Option<Range<u64>>
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat, range: Option<Range<u64>>) -> TraceWriter<W> {
+        TraceWriter {
+            out,
+            format,
+            range
+        }
+    }
+
+    /// Give back what the trace was written to
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_text(&mut self, event: &TraceEvent) -> std::io::Result<()> {
+        let bytes: Vec<String> = event.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let mut line = format!("{:>8}  {:<32}  {:<24}", event.pc, bytes.join(" "), event.instruction_text());
+
+        for change in &event.registers {
+            line += &format!("  {}: {} -> {}", REGISTER_NAMES[change.register], change.old, change.new);
+        }
+
+        for write in &event.memory {
+            let bytes: Vec<String> = write.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            line += &format!("  [{}]: {}", write.address, bytes.join(" "));
+        }
+
+        if let Some(fault) = &event.fault {
+            line += &format!("  fault: {}", fault.kind);
+        }
+
+        writeln!(self.out, "{}", line.trim_end())
+    }
+
+    fn write_json(&mut self, event: &TraceEvent) -> std::io::Result<()> {
+        let bytes: Vec<String> = event.bytes.iter().map(|byte| byte.to_string()).collect();
+        let registers: Vec<String> = event.registers.iter().map(|change| {
+            format!("\"{}\":{}", REGISTER_NAMES[change.register], change.new)
+        }).collect();
+        let memory: Vec<String> = event.memory.iter().map(|write| {
+            let bytes: Vec<String> = write.bytes.iter().map(|byte| byte.to_string()).collect();
+            format!("{{\"address\":{},\"bytes\":[{}]}}", write.address, bytes.join(","))
+        }).collect();
+
+        write!(
+            self.out,
+            "{{\"pc\":{},\"bytes\":[{}],\"instruction\":\"{}\",\"registers\":{{{}}},\"memory\":[{}]",
+            event.pc,
+            bytes.join(","),
+            event.instruction_text(),
+            registers.join(","),
+            memory.join(",")
+        )?;
+
+        if let Some(fault) = &event.fault {
+            write!(self.out, ",\"fault\":\"{}\"", fault.kind)?;
+        }
+
+        writeln!(self.out, "}}")
+    }
+}
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        if let Some(range) = &self.range {
+            if !range.contains(&event.pc) {
+                return;
+            }
+        }
+
+        // a trace that can't be written shouldn't stop the program
+        let _ = match self.format {
+            TraceFormat::Text => self.write_text(event),
+            TraceFormat::Json => self.write_json(event)
+        };
+    }
+}
+
+/// Which registers differ between `before` and `after`
+///
+/// the program counter is left out when it simply moved on to `next_pc`
+pub(crate) fn changed_registers(before: &[u64; 16], after: &[u64; 16], next_pc: u64) -> Vec<RegisterChange> {
+    (0..16).filter(|register| {
+        before[*register] != after[*register] && (*register != COUNTER_REG || after[COUNTER_REG] != next_pc)
+    }).map(|register| RegisterChange {
+        register,
+        old: before[register],
+        new: after[register]
+    }).collect()
+}
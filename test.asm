@@ -1,10 +1,27 @@
-; count to 100 loop
+; count to 100 and print every number
 :loop
-move a f
-move byte b 1
-add
-move f c
-move a c
-move byte b 100
-less
+add g g 1
+move a g
+call print_number
+less c g 100
 jump loop true
+halt
+
+; print the number in a on its own line
+:print_number
+move byte b 0 ; digits pushed so far
+:push_digit
+rem d a 10
+add d d 48 ; '0'
+push byte d
+add b b 1
+div a a 10
+jnz push_digit
+:print_digit
+pop byte d
+write byte d 0xfffffffff0000000 ; console data port
+sub b b 1
+jnz print_digit
+move byte d 10 ; '\n'
+write byte d 0xfffffffff0000000
+ret
@@ -0,0 +1,56 @@
+use c64::assembler::{assemble, Severity};
+
+#[test]
+fn reports_every_error_with_its_position() {
+    let diagnostics = assemble("move q 1\nmove byte a 300\njump nowhere\nhalt\n").unwrap_err();
+
+    let positions: Vec<(Severity, usize, usize)> = diagnostics.iter().map(|diagnostic| {
+        (diagnostic.severity, diagnostic.line, diagnostic.column)
+    }).collect();
+
+    assert_eq!(positions, [(Severity::Error, 1, 6), (Severity::Error, 2, 13), (Severity::Error, 3, 6)]);
+    assert_eq!(diagnostics[1].message, "300 doesn't fit in a byte");
+}
+
+#[test]
+fn renders_a_caret_under_the_word() {
+    let diagnostics = assemble("  read obyte a zz ; comment\n").unwrap_err();
+
+    assert_eq!(
+        diagnostics[0].render("test.asm"),
+        "error: `zz` is not an address\n --> test.asm:1:16\n  |\n1 |   read obyte a zz ; comment\n  |                ^^"
+    );
+}
+
+#[test]
+fn warns_about_unused_labels_and_unknown_instructions() {
+    let assembly = assemble(":unused\nfrobnicate a\nhalt\n").unwrap();
+
+    assert_eq!(assembly.bin, [24]);
+
+    let warnings: Vec<&str> = assembly.warnings.iter().map(|warning| warning.message.as_str()).collect();
+    assert_eq!(warnings, ["label `unused` is never used", "unknown instruction `frobnicate`, the line is ignored"]);
+}
+
+#[test]
+fn negative_values_are_twos_complement() {
+    let assembly = assemble("move byte a -1\nmove dbyte a -32768\nhalt\n").unwrap();
+
+    assert_eq!(assembly.bin, [2, 0, 0, 255, 2, 1, 0, 0x80, 0, 24]);
+
+    let diagnostics = assemble("move byte a -129\n").unwrap_err();
+    assert_eq!(diagnostics[0].message, "-129 doesn't fit in a byte");
+}
+
+#[test]
+fn negative_alu_values_need_an_obyte() {
+    let diagnostics = assemble("add byte b a -1\nless_signed dbyte c a -6\n").unwrap_err();
+
+    let messages: Vec<&str> = diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect();
+    assert_eq!(messages, [
+        "-1 would be zero extended from a byte and lose its sign, leave out the type or use obyte",
+        "-6 would be zero extended from a dbyte and lose its sign, leave out the type or use obyte"
+    ]);
+
+    assert_eq!(assemble("add b a -1").unwrap().bin, assemble("add obyte b a -1").unwrap().bin);
+}
@@ -0,0 +1,182 @@
+use std::{cell::RefCell, rc::Rc};
+
+use c64::{
+    assembler::assemble,
+    bus::Device,
+    devices::IO_BASE,
+    emulator::{Budget, Emulator, FaultKind, RunOutcome, COUNTER_REG, STACK_REG},
+    ram::PAGE_SIZE
+};
+
+/// Remembers what was written to it and counts up on every read
+#[derive(Default)]
+struct Recorder {
+    writes: Vec<(u64, u8)>,
+    reads: u8,
+    ticks: u64
+}
+
+struct SharedRecorder(Rc<RefCell<Recorder>>);
+
+impl Device for SharedRecorder {
+    fn read(&mut self, _offset: u64) -> u8 {
+        let mut recorder = self.0.borrow_mut();
+        recorder.reads += 1;
+        recorder.reads
+    }
+
+    fn write(&mut self, offset: u64, value: u8) {
+        self.0.borrow_mut().writes.push((offset, value));
+    }
+
+    fn tick(&mut self, _cycles: u64) {
+        self.0.borrow_mut().ticks += 1;
+    }
+}
+
+#[test]
+fn device_sees_reads_and_writes_relative_to_its_base() {
+    let bin = assemble("
+        move dbyte a 513
+        write dbyte a 1000000
+        read byte b 1000003
+        read byte c 1000003
+        halt
+    ").unwrap().bin;
+
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(1_000_000, 4, Box::new(SharedRecorder(recorder.clone())));
+
+    assert_eq!(emulator.run(Budget::Unlimited), RunOutcome::Halted);
+    assert_eq!(emulator.register(1), 1);
+    assert_eq!(emulator.register(2), 2);
+
+    let recorder = recorder.borrow();
+    assert_eq!(recorder.writes, [(0, 2), (1, 1)]);
+    assert_eq!(recorder.ticks, 5);
+}
+
+#[test]
+fn unmapped_addresses_fault_without_touching_devices() {
+    let bin = assemble("
+        move byte a 1
+        write dbyte a 1000003
+        halt
+    ").unwrap().bin;
+
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(1_000_000, 4, Box::new(SharedRecorder(recorder.clone())));
+
+    match emulator.run(Budget::Unlimited) {
+        RunOutcome::Fault(fault) => assert_eq!(fault.kind, FaultKind::BadAddress(1_000_004)),
+        outcome => panic!("expected a fault, got {:?}", outcome)
+    }
+    assert!(recorder.borrow().writes.is_empty());
+}
+
+#[test]
+#[should_panic(expected = "overlaps another device")]
+fn devices_cant_overlap() {
+    let mut emulator = Emulator::new(&[]);
+
+    emulator.bus_mut().map(1_000_000, 4, Box::new(SharedRecorder(Rc::default())));
+    emulator.bus_mut().map(1_000_002, 4, Box::new(SharedRecorder(Rc::default())));
+}
+
+#[test]
+fn large_ram_is_only_allocated_where_written() {
+    let bin = assemble("
+        move obyte a 0x3fffffff8
+        move obyte b 0x1122334455667788
+        write obyte b a
+        read obyte c a
+        read obyte d 0x100000000 ; never written, reads as 0
+        halt
+    ").unwrap().bin;
+
+    let size = 16 << 30;
+    let mut emulator = Emulator::with_ram_size(&bin, size);
+    emulator.set_register(3, 7);
+
+    assert_eq!(emulator.register(STACK_REG), size - 1);
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+    assert_eq!(emulator.register(2), 0x1122334455667788);
+    assert_eq!(emulator.register(3), 0);
+
+    // the page with the program and the one at the very top
+    assert_eq!(emulator.bus().ram().allocated(), 2 * PAGE_SIZE);
+}
+
+#[test]
+fn instructions_and_reads_can_cross_pages() {
+    let bin = assemble("
+        move obyte a 0x1122334455667788
+        write obyte a 131070
+        read obyte b 131070
+        halt
+    ").unwrap().bin;
+
+    // the first instruction starts 3 bytes before the end of the first page
+    let start = PAGE_SIZE - 3;
+    let mut emulator = Emulator::with_ram_size(&[], 3 * PAGE_SIZE);
+    emulator.load_at(start, &bin).unwrap();
+    emulator.set_register(COUNTER_REG, start);
+
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+    assert_eq!(emulator.register(1), 0x1122334455667788);
+    assert_eq!(emulator.memory(start..start + bin.len() as u64).unwrap(), bin.as_slice());
+    assert_eq!(emulator.memory(131070..131078).as_deref(), Some(&0x1122334455667788u64.to_be_bytes()[..]));
+}
+
+#[test]
+fn small_ram_ends_where_it_says() {
+    let bin = assemble("
+        move byte a 1
+        write byte a 99
+        write byte a 100
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::with_ram_size(&bin, 100);
+    assert_eq!(emulator.register(STACK_REG), 99);
+
+    let RunOutcome::Fault(fault) = emulator.run(Budget::Steps(10)) else {
+        panic!("expected a fault");
+    };
+    assert_eq!(fault.kind, FaultKind::BadAddress(100));
+    assert_eq!(emulator.memory(99..100).as_deref(), Some(&[1][..]));
+    assert_eq!(emulator.memory(99..101), None);
+}
+
+#[test]
+fn ram_can_reach_all_the_way_to_the_devices() {
+    let bin = assemble("
+        move obyte b 0x1122334455667788
+        write obyte b a
+        read obyte c a
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::with_ram_size(&bin, IO_BASE);
+    emulator.set_register(0, IO_BASE - 8);
+
+    assert_eq!(emulator.register(STACK_REG), IO_BASE - 1);
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+    assert_eq!(emulator.register(2), 0x1122334455667788);
+}
+
+#[test]
+fn an_instruction_cut_off_by_the_end_of_ram_faults_there() {
+    let bin = assemble("move obyte a 0x1122334455667788").unwrap().bin;
+
+    let mut emulator = Emulator::with_ram_size(&[], 100);
+    emulator.load_at(96, &bin[..4]).unwrap();
+    emulator.set_register(COUNTER_REG, 96);
+
+    let RunOutcome::Fault(fault) = emulator.run(Budget::Steps(10)) else {
+        panic!("expected a fault");
+    };
+    assert_eq!(fault.kind, FaultKind::BadAddress(100));
+}
@@ -0,0 +1,20 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc
+};
+
+/// Output that can still be looked at after it's handed to a device or the syscalls
+#[derive(Clone, Default)]
+pub struct SharedOutput(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    cell::RefCell,
+    io::{self, Cursor, Write},
+    rc::Rc,
+    sync::mpsc,
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+use c64::{
+    assembler::assemble,
+    devices::{
+        console::{Console, CONSOLE_BASE, CONSOLE_LEN},
+        disk::{Disk, DISK_BASE, DISK_LEN, SECTOR_SIZE, STATUS_ERROR},
+        framebuffer::{Framebuffer, DEFAULT_PALETTE, FRAMEBUFFER_BASE, FRAMEBUFFER_LEN},
+        random::{Random, RANDOM_BASE, RANDOM_LEN},
+        rtc::{Rtc, RTC_BASE, RTC_LEN},
+        timer::{Timer, TIMER_BASE, TIMER_LEN},
+        uart::{Uart, UART_BASE, UART_LEN}
+    },
+    emulator::{Budget, Emulator, RunOutcome}
+};
+
+mod common;
+
+use common::SharedOutput;
+
+#[test]
+fn console_echoes_input_until_it_closes() {
+    let bin = assemble("
+        :wait
+        read byte a 0xfffffffff0000001 ; status
+        and b a 2
+        jnz done
+        and b a 1
+        jz wait
+        read byte c 0xfffffffff0000000
+        add c c 1
+        write byte c 0xfffffffff0000000
+        jump wait
+        :done
+        halt
+    ").unwrap().bin;
+
+    let (sender, receiver) = mpsc::channel();
+    for byte in b"HAL" {
+        sender.send(*byte).unwrap();
+    }
+    drop(sender);
+
+    let output = SharedOutput::default();
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(CONSOLE_BASE, CONSOLE_LEN, Box::new(Console::new(output.clone(), Some(receiver))));
+
+    assert_eq!(emulator.run(Budget::Steps(1000)), RunOutcome::Halted);
+    assert_eq!(output.0.borrow().as_slice(), b"IBM");
+}
+
+#[test]
+fn console_without_input_reads_zero() {
+    let bin = assemble("
+        read byte a 0xfffffffff0000000
+        read byte b 0xfffffffff0000001
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.set_register(0, 7);
+    emulator.bus_mut().map(CONSOLE_BASE, CONSOLE_LEN, Box::new(Console::new(io::sink(), None)));
+
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+    assert_eq!(emulator.register(0), 0);
+    assert_eq!(emulator.register(1), 2);
+}
+
+#[test]
+fn timer_interrupts_every_reload_cycles() {
+    let bin = assemble("
+        vectors table
+        move obyte a 100
+        write obyte a 0xfffffffff0000010 ; count
+        write obyte a 0xfffffffff0000018 ; reload
+        move byte a 3
+        write byte a 0xfffffffff0000020 ; enable with interrupts
+        enable_interrupts
+        :spin
+        less c g 5
+        jump spin true
+        read obyte h 0xfffffffff0000028 ; cycles
+        halt
+
+        :table
+        obyte on_timer
+
+        :on_timer
+        add g g 1
+        write byte g 0xfffffffff0000021 ; clear expired
+        iret
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map_with_irq(TIMER_BASE, TIMER_LEN, 0, Box::new(Timer::new()));
+
+    assert_eq!(emulator.run(Budget::Steps(10_000)), RunOutcome::Halted);
+    assert_eq!(emulator.register(6), 5);
+
+    // five periods of 100 cycles went by, give or take the instructions around them
+    let cycles = emulator.register(7);
+    assert!((500..600).contains(&cycles), "{} cycles", cycles);
+    assert!(emulator.cycles() >= cycles);
+}
+
+#[test]
+fn timer_without_reload_goes_off_once() {
+    let bin = assemble("
+        move byte a 10
+        write obyte a 0xfffffffff0000010 ; count
+        move byte a 1
+        write byte a 0xfffffffff0000020 ; enable
+        :wait
+        read byte c 0xfffffffff0000021 ; status
+        jump wait false
+        read obyte d 0xfffffffff0000010 ; count
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(TIMER_BASE, TIMER_LEN, Box::new(Timer::new()));
+
+    assert_eq!(emulator.run(Budget::Steps(1000)), RunOutcome::Halted);
+    assert_eq!(emulator.register(3), 0);
+}
+
+#[test]
+fn framebuffer_draws_text_in_cell_colors() {
+    let bin = assemble("
+        move byte a 0x48 ; H
+        write byte a 0xfffffffff0011029 ; row 1, column 1
+        move byte a 0x21 ; white on red
+        write byte a 0xfffffffff0011429
+        halt
+    ").unwrap().bin;
+
+    let framebuffer = Rc::new(RefCell::new(Framebuffer::new()));
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(FRAMEBUFFER_BASE, FRAMEBUFFER_LEN, Box::new(framebuffer.clone()));
+
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+
+    let image = framebuffer.borrow().render();
+    assert_eq!((image.width, image.height), (320, 200));
+
+    // the top row of an H is two bars, 0x33
+    let top_row: Vec<[u8; 3]> = (8..16).map(|x| image.pixel(x, 8)).collect();
+    let white = DEFAULT_PALETTE[1];
+    let red = DEFAULT_PALETTE[2];
+    assert_eq!(top_row, [white, white, red, red, white, white, red, red]);
+
+    // an untouched cell is light blue on blue and a space is all background
+    assert_eq!(image.pixel(0, 0), DEFAULT_PALETTE[6]);
+}
+
+#[test]
+fn framebuffer_draws_bitmap_with_palette() {
+    let bin = assemble("
+        move byte a 1
+        write byte a 0xfffffffff0010000 ; bitmap mode
+        move byte a 0x12
+        write byte a 0xfffffffff001012d ; palette entry 15, red
+        move byte a 0x34
+        write byte a 0xfffffffff001012e ; green
+        move byte a 0x56
+        write byte a 0xfffffffff001012f ; blue
+        move byte a 0xff ; only the low nibble counts
+        write byte a 0xfffffffff0020141 ; x 1, y 1
+        halt
+    ").unwrap().bin;
+
+    let framebuffer = Rc::new(RefCell::new(Framebuffer::new()));
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(FRAMEBUFFER_BASE, FRAMEBUFFER_LEN, Box::new(framebuffer.clone()));
+
+    assert_eq!(emulator.run(Budget::Steps(20)), RunOutcome::Halted);
+
+    let image = framebuffer.borrow().render();
+    assert_eq!(image.pixel(1, 1), [0x12, 0x34, 0x56]);
+    assert_eq!(image.pixel(0, 0), DEFAULT_PALETTE[0]);
+}
+
+#[test]
+fn framebuffer_saves_snapshots_when_asked() {
+    let dir = std::env::temp_dir().join(format!("c64-snapshots-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let bin = assemble("
+        write byte a 0xfffffffff0010001
+        move byte a 0x41
+        write byte a 0xfffffffff0011000
+        write byte a 0xfffffffff0010001
+        halt
+    ").unwrap().bin;
+
+    let framebuffer = Rc::new(RefCell::new(Framebuffer::with_snapshots(dir.join("frame.png"))));
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(FRAMEBUFFER_BASE, FRAMEBUFFER_LEN, Box::new(framebuffer.clone()));
+
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+    assert_eq!(framebuffer.borrow().snapshots(), 2);
+
+    let first = std::fs::read(dir.join("frame-0.png")).unwrap();
+    let second = std::fs::read(dir.join("frame-1.png")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(second, framebuffer.borrow().render().to_png());
+    assert_ne!(first, second);
+
+    assert_eq!(&first[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&first[12..16], b"IHDR");
+    // an empty IEND chunk always has the same crc
+    assert_eq!(&first[first.len() - 12..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+}
+
+#[test]
+fn framebuffer_keeps_snapshot_errors_for_the_host() {
+    let path = std::env::temp_dir().join(format!("c64-missing-{}", std::process::id())).join("frame.png");
+
+    let bin = assemble("
+        write byte a 0xfffffffff0010001
+        halt
+    ").unwrap().bin;
+
+    let framebuffer = Rc::new(RefCell::new(Framebuffer::with_snapshots(&path)));
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(FRAMEBUFFER_BASE, FRAMEBUFFER_LEN, Box::new(framebuffer.clone()));
+
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+
+    let framebuffer = framebuffer.borrow();
+    assert_eq!(framebuffer.snapshots(), 1);
+    assert_eq!(framebuffer.snapshot_errors().len(), 1);
+    assert_eq!(framebuffer.snapshot_errors()[0].0, path.with_file_name("frame-0.png"));
+}
+
+#[test]
+fn image_saves_ppm() {
+    let framebuffer = Framebuffer::new();
+    let ppm = framebuffer.render().to_ppm();
+
+    let header = b"P6\n320 200\n255\n";
+    assert_eq!(&ppm[..header.len()], header);
+    assert_eq!(ppm.len(), header.len() + 320 * 200 * 3);
+    assert_eq!(&ppm[header.len()..header.len() + 3], &DEFAULT_PALETTE[6]);
+}
+
+#[test]
+fn disk_copies_sectors_to_and_from_ram() {
+    let bin = assemble("
+        move dbyte a 0x1234
+        write dbyte a 10000
+        move byte a 1
+        write obyte a 0xfffffffff0000040 ; sector
+        move dbyte a 10000
+        write obyte a 0xfffffffff0000048 ; buffer
+        move byte a 2
+        write byte a 0xfffffffff0000058 ; write
+        call wait
+
+        move byte a 0
+        write obyte a 0xfffffffff0000040 ; sector
+        move dbyte a 20000
+        write obyte a 0xfffffffff0000048 ; buffer
+        move byte a 1
+        write byte a 0xfffffffff0000058 ; read
+        call wait
+
+        read dbyte b 20000
+        read obyte c 0xfffffffff0000050 ; sectors
+        halt
+
+        :wait
+        read byte d 0xfffffffff0000059 ; status
+        and e d 1
+        jnz wait
+        ret
+    ").unwrap().bin;
+
+    // one and a half sectors, the missing half reads as zeros
+    let mut image = vec![0; SECTOR_SIZE + SECTOR_SIZE / 2];
+    image[0] = 0xAB;
+    image[1] = 0xCD;
+
+    let disk = Rc::new(RefCell::new(Disk::new(Cursor::new(image)).unwrap()));
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(DISK_BASE, DISK_LEN, Box::new(disk.clone()));
+
+    assert_eq!(emulator.run(Budget::Steps(1000)), RunOutcome::Halted);
+    assert_eq!(emulator.register(1), 0xABCD);
+    assert_eq!(emulator.register(2), 2);
+    assert_eq!(emulator.register(3), 0);
+
+    let image = disk.borrow().image().get_ref().clone();
+    assert_eq!(image.len(), 2 * SECTOR_SIZE);
+    assert_eq!(&image[SECTOR_SIZE..SECTOR_SIZE + 2], &[0x12, 0x34]);
+}
+
+#[test]
+fn disk_reports_errors() {
+    let bin = assemble("
+        move byte a 5
+        write obyte a 0xfffffffff0000040 ; sector past the end
+        move byte a 1
+        write byte a 0xfffffffff0000058 ; read
+        read byte b 0xfffffffff0000059 ; status
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(DISK_BASE, DISK_LEN, Box::new(Disk::new(Cursor::new(vec![0; SECTOR_SIZE])).unwrap()));
+
+    assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+    // the command finishes at the end of the instruction that started it
+    assert_eq!(emulator.register(1) as u8, STATUS_ERROR);
+}
+
+/// Sends back every byte it gets plus one, until the line closes or it has sent a newline plus one
+const UART_ECHO: &str = "
+    :wait
+    read byte a 0xfffffffff0000061 ; status
+    and b a 2
+    jnz done
+    and b a 1
+    jz wait
+    read byte c 0xfffffffff0000060
+    add c c 1
+    write byte c 0xfffffffff0000060
+    equal b c 11
+    jnz wait
+    :done
+    halt
+";
+
+#[test]
+fn uart_echoes_until_the_line_closes() {
+    let bin = assemble(UART_ECHO).unwrap().bin;
+
+    let (sender, receiver) = mpsc::channel();
+    for byte in b"HAL" {
+        sender.send(*byte).unwrap();
+    }
+    drop(sender);
+
+    let output = SharedOutput::default();
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(UART_BASE, UART_LEN, Box::new(Uart::new(output.clone(), receiver)));
+
+    assert_eq!(emulator.run(Budget::Steps(1000)), RunOutcome::Halted);
+    assert_eq!(output.0.borrow().as_slice(), b"IBM");
+}
+
+#[cfg(unix)]
+#[test]
+fn uart_talks_over_a_unix_socket() {
+    use std::{io::Read, net::Shutdown, os::unix::net::UnixListener};
+
+    let path = std::env::temp_dir().join(format!("c64-uart-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let harness = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"HAL").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        reply
+    });
+
+    let bin = assemble(UART_ECHO).unwrap().bin;
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(UART_BASE, UART_LEN, Box::new(Uart::unix_socket(&path).unwrap()));
+
+    assert_eq!(emulator.run(Budget::Unlimited), RunOutcome::Halted);
+    // the harness only stops reading once the uart hangs up
+    drop(emulator);
+
+    assert_eq!(harness.join().unwrap(), b"IBM");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn uart_talks_over_a_pty() {
+    use std::{fs::OpenOptions, io::Read};
+
+    let (uart, path) = Uart::pty().unwrap();
+    let mut client = OpenOptions::new().read(true).write(true).open(path).unwrap();
+    client.write_all(b"HAL\n").unwrap();
+
+    let bin = assemble(UART_ECHO).unwrap().bin;
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(UART_BASE, UART_LEN, Box::new(uart));
+
+    assert_eq!(emulator.run(Budget::Unlimited), RunOutcome::Halted);
+
+    // raw mode, so nothing was echoed back or turned into a different line ending
+    let mut reply = [0; 4];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"IBM\x0b");
+}
+
+#[test]
+fn rtc_reads_a_frozen_time() {
+    let bin = assemble("
+        read obyte a 0xfffffffff0000080 ; seconds
+        read obyte b 0xfffffffff0000088 ; nanoseconds
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(RTC_BASE, RTC_LEN, Box::new(Rtc::frozen(Duration::new(1_700_000_000, 123_456_789))));
+
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+    assert_eq!(emulator.register(0), 1_700_000_000);
+    assert_eq!(emulator.register(1), 123_456_789);
+}
+
+#[test]
+fn rtc_follows_the_host_clock_with_an_offset() {
+    let bin = assemble("
+        read obyte a 0xfffffffff0000080 ; seconds
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(RTC_BASE, RTC_LEN, Box::new(Rtc::with_offset(-3600)));
+
+    let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+    let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+    assert!((before - 3600..=after - 3600).contains(&emulator.register(0)));
+}
+
+#[test]
+fn random_repeats_for_the_same_seed() {
+    let bin = assemble("
+        read obyte a 0xfffffffff0000090
+        read obyte b 0xfffffffff0000090
+        move byte c 0
+        write obyte c 0xfffffffff0000098 ; seed
+        read obyte d 0xfffffffff0000090
+        halt
+    ").unwrap().bin;
+
+    let run = |seed| {
+        let mut emulator = Emulator::new(&bin);
+        emulator.bus_mut().map(RANDOM_BASE, RANDOM_LEN, Box::new(Random::new(seed)));
+        assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Halted);
+        (emulator.register(0), emulator.register(1), emulator.register(3))
+    };
+
+    let (first, second, reseeded) = run(42);
+    assert_ne!(first, second);
+    assert_eq!(run(42), (first, second, reseeded));
+    assert_ne!(run(43).0, first);
+
+    // the first number splitmix64 gives for seed 0
+    assert_eq!(reseeded, 0xE220_A839_7B1D_CDAF);
+}
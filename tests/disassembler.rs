@@ -0,0 +1,52 @@
+use c64::{assembler::assemble, disassembler::disassemble};
+
+const PROGRAM: &str = "
+    move byte a 1
+    :loop
+    add a a 1
+    less c a 10
+    jump loop true
+    call done
+    :done
+    halt
+    ; 255 isn't an opcode
+    byte 255
+";
+
+#[test]
+fn jump_targets_get_labels() {
+    let bin = assemble(PROGRAM).unwrap().bin;
+    let listing = disassemble(&bin);
+
+    // the move before the loop takes 4 bytes
+    assert!(listing.contains(":label_4\n"), "{}", listing);
+    assert!(listing.contains("jump label_4 true"), "{}", listing);
+
+    let call = listing.lines().find(|line| line.trim_start().starts_with("call ")).unwrap();
+    let target = call.split_whitespace().nth(1).unwrap();
+    assert!(target.starts_with("label_"), "{}", call);
+    assert!(listing.contains(&format!(":{}\n    halt", target)), "{}", listing);
+}
+
+#[test]
+fn bytes_that_dont_decode_are_written_as_data() {
+    let bin = assemble(PROGRAM).unwrap().bin;
+    let listing = disassemble(&bin);
+    let last = listing.lines().last().unwrap();
+
+    assert!(last.trim_start().starts_with("byte 255 "), "{}", last);
+    assert!(last.ends_with(&format!("{}: ff", bin.len() - 1)), "{}", last);
+}
+
+#[test]
+fn disassembly_assembles_back_to_the_same_binary() {
+    let bin = assemble(PROGRAM).unwrap().bin;
+
+    assert_eq!(assemble(&disassemble(&bin)).unwrap().bin, bin);
+
+    // a jump into the middle of an instruction can't be a label and stays an address
+    let bin = assemble("jump 1\nhalt").unwrap().bin;
+    let listing = disassemble(&bin);
+    assert!(listing.contains("jump 1 "), "{}", listing);
+    assert_eq!(assemble(&listing).unwrap().bin, bin);
+}
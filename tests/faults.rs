@@ -0,0 +1,49 @@
+use c64::{
+    assembler::assemble,
+    emulator::{Emulator, Fault, FaultKind, COUNTER_REG, RAM_SIZE}
+};
+
+/// Run `bin` until it faults, making sure the fault is also what `step` returns and that the
+/// program counter stays on the instruction that caused it
+fn fault(bin: &[u8]) -> Fault {
+    let mut emulator = Emulator::new(bin);
+
+    for _ in 0..100 {
+        if let Err(fault) = emulator.step() {
+            assert_eq!(emulator.register(COUNTER_REG), fault.pc);
+            assert!(!emulator.is_halted());
+
+            // stepping again hits the same fault
+            assert_eq!(emulator.step(), Err(fault));
+            return fault;
+        }
+    }
+
+    panic!("expected a fault");
+}
+
+#[test]
+fn unknown_opcodes_fault() {
+    let mut bin = assemble("nop\nnop").unwrap().bin;
+    bin.push(255);
+
+    assert_eq!(fault(&bin), Fault { pc: 2, opcode: Some(255), kind: FaultKind::BadOpcode });
+}
+
+#[test]
+fn bad_operands_fault() {
+    // move with type 4, there are only 0 to 3
+    let bin = [0, 2, 4, 0, 0];
+    assert_eq!(fault(&bin), Fault { pc: 1, opcode: Some(2), kind: FaultKind::BadOperandType(4) });
+
+    // pop into register 16, there are only 0 to 15
+    let bin = [0, 0, 9, 0, 16];
+    assert_eq!(fault(&bin), Fault { pc: 2, opcode: Some(9), kind: FaultKind::BadRegister(16) });
+}
+
+#[test]
+fn reading_past_the_end_of_ram_faults() {
+    let bin = assemble(&format!("move byte a 1\nread obyte b {}\nhalt", RAM_SIZE - 4)).unwrap().bin;
+
+    assert_eq!(fault(&bin), Fault { pc: 4, opcode: Some(3), kind: FaultKind::BadAddress(RAM_SIZE as u64) });
+}
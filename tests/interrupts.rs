@@ -0,0 +1,111 @@
+use std::{cell::Cell, rc::Rc};
+
+use c64::{
+    assembler::assemble,
+    bus::Device,
+    emulator::{Budget, Emulator, FaultKind, Flags, RunOutcome, RAM_SIZE, STACK_REG}
+};
+
+/// Raises its line every `period` ticks until the program writes to it
+struct Ticker {
+    period: u64,
+    ticks: u64,
+    raised: Rc<Cell<bool>>
+}
+
+impl Device for Ticker {
+    fn read(&mut self, _offset: u64) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _offset: u64, _value: u8) {
+        self.raised.set(false);
+    }
+
+    fn tick(&mut self, _cycles: u64) {
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(self.period) {
+            self.raised.set(true);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.raised.get()
+    }
+}
+
+const PROGRAM: &str = "
+    vectors table
+    enable_interrupts
+    :spin
+    less c g 3
+    jump spin true
+    disable_interrupts
+    halt
+
+    ; line 0 is unused
+    :table
+    obyte 0
+    obyte on_tick
+
+    :on_tick
+    add g g 1
+    write byte g 0xfffffffff0000000 ; lower the line
+    iret
+";
+
+#[test]
+fn interrupts_run_the_handler_and_return() {
+    let bin = assemble(PROGRAM).unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map_with_irq(0xffff_ffff_f000_0000, 1, 1, Box::new(Ticker { period: 10, ticks: 0, raised: Rc::default() }));
+
+    assert_eq!(emulator.run(Budget::Steps(1000)), RunOutcome::Halted);
+    assert_eq!(emulator.register(6), 3);
+    assert!(!emulator.interrupts_enabled());
+    // every handler cleaned up after itself
+    assert_eq!(emulator.register(STACK_REG), RAM_SIZE as u64 - 1);
+}
+
+#[test]
+fn interrupts_are_ignored_while_disabled() {
+    let bin = assemble("
+        :spin
+        add a a 1
+        less c a 50
+        jump spin true
+        halt
+    ").unwrap().bin;
+
+    let raised = Rc::new(Cell::new(true));
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map_with_irq(0xffff_ffff_f000_0000, 1, 0, Box::new(Ticker { period: 1, ticks: 0, raised: raised.clone() }));
+
+    assert_eq!(emulator.run(Budget::Steps(1000)), RunOutcome::Halted);
+    assert!(raised.get());
+}
+
+#[test]
+fn flags_survive_being_pushed() {
+    for byte in 0..16 {
+        assert_eq!(Flags::from_byte(byte).to_byte(), byte);
+    }
+}
+
+#[test]
+fn an_interrupt_that_doesnt_fit_on_the_stack_pushes_nothing() {
+    let bin = assemble(PROGRAM).unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    // room for the pc but not the flags after it
+    emulator.set_stack(1000..1008);
+    emulator.bus_mut().map_with_irq(0xffff_ffff_f000_0000, 1, 1, Box::new(Ticker { period: 10, ticks: 0, raised: Rc::default() }));
+
+    let RunOutcome::Fault(fault) = emulator.run(Budget::Steps(1000)) else {
+        panic!("expected a fault");
+    };
+    assert_eq!(fault.kind, FaultKind::StackOverflow);
+    assert_eq!(emulator.register(STACK_REG), 1007);
+    assert!(emulator.interrupts_enabled());
+}
@@ -0,0 +1,435 @@
+use c64::{
+    assembler::assemble,
+    devices::console::{Console, CONSOLE_BASE, CONSOLE_LEN},
+    emulator::{Budget, Emulator, Flags, RunOutcome, COUNTER_REG, RAM_SIZE, STACK_REG},
+    isa::{decode, AluOp, DataType, DecodeError, FlagCondition, Instruction, InstructionDef, INSTRUCTIONS, MAX_INSTRUCTION_LEN}
+};
+
+/// At least one instruction for every opcode, with every `<type>` where there is one
+fn samples() -> Vec<Instruction> {
+    let mut samples = vec![
+        Instruction::Nop,
+        Instruction::MoveRegister { dst: 5, src: 2 },
+        Instruction::Jump { address: 0x0102_0304_0506_0708 },
+        Instruction::JumpRegister { register: 15 },
+        Instruction::JumpIf { condition: true, address: 26 },
+        Instruction::JumpRegisterIf { condition: false, register: 3 },
+        Instruction::Add,
+        Instruction::Sub,
+        Instruction::Mul,
+        Instruction::Div,
+        Instruction::Equal,
+        Instruction::Less,
+        Instruction::Not,
+        Instruction::And,
+        Instruction::Or,
+        Instruction::Xor,
+        Instruction::Halt,
+        Instruction::Call { address: 77 },
+        Instruction::CallRegister { register: 7 },
+        Instruction::Ret,
+        Instruction::NotRegister { dst: 0, src: 9 },
+        Instruction::Neg { dst: 1, src: 1 },
+        Instruction::Syscall { number: 4 },
+        Instruction::Iret,
+        Instruction::EnableInterrupts,
+        Instruction::DisableInterrupts,
+        Instruction::Vectors { address: 4096 },
+        Instruction::VectorsRegister { register: 2 }
+    ];
+
+    for condition in FlagCondition::ALL {
+        samples.push(Instruction::JumpFlags { condition, address: 1234 });
+        samples.push(Instruction::JumpRegisterFlags { condition, register: 11 });
+    }
+
+    for op in AluOp::ALL {
+        samples.push(Instruction::AluRegister { op, dst: 3, src1: 4, src2: 15 });
+        samples.push(Instruction::AluValue { op, ty: DataType::Byte, dst: 3, src: 3, value: 9 });
+    }
+
+    for ty in [DataType::Byte, DataType::DByte, DataType::QByte, DataType::OByte] {
+        samples.extend([
+            Instruction::MoveValue { ty, register: 1, value: ty.max_value() },
+            Instruction::ReadAddress { ty, register: 2, address: 300 },
+            Instruction::ReadRegister { ty, register: 2, address_register: 13 },
+            Instruction::ReadSignedAddress { ty, register: 12, address: 4 },
+            Instruction::ReadSignedRegister { ty, register: 12, address_register: 1 },
+            Instruction::WriteAddress { ty, register: 3, address: u64::MAX },
+            Instruction::WriteRegister { ty, register: 3, address_register: 0 },
+            Instruction::PushRegister { ty, register: 4 },
+            Instruction::PushValue { ty, value: 1 },
+            Instruction::Pop { ty, register: 14 },
+            Instruction::AluValue { op: AluOp::ALL[ty.index() as usize], ty, dst: 6, src: 7, value: ty.max_value() }
+        ]);
+    }
+
+    samples
+}
+
+#[test]
+fn opcodes_are_unique() {
+    for (i, def) in INSTRUCTIONS.iter().enumerate() {
+        assert!(INSTRUCTIONS[i + 1..].iter().all(|other| other.opcode != def.opcode), "opcode {} is defined twice", def.opcode);
+        assert_eq!(InstructionDef::get(def.opcode), Some(def));
+    }
+}
+
+#[test]
+fn mnemonic_and_operand_form_pick_out_one_definition() {
+    for (i, def) in INSTRUCTIONS.iter().enumerate() {
+        let clash = INSTRUCTIONS[i + 1..].iter().find(|other| other.mnemonic == def.mnemonic && other.operands == def.operands);
+        assert_eq!(clash, None, "opcode {} has the same mnemonic and operands as another one", def.opcode);
+    }
+}
+
+#[test]
+fn from_operands_rejects_operands_the_opcode_doesnt_take() {
+    let read = Instruction::ReadAddress { ty: DataType::Byte, register: 1, address: 8 };
+    let signed = Instruction::ReadSignedAddress { ty: DataType::Byte, register: 1, address: 8 };
+
+    assert_eq!(Instruction::from_operands(read.opcode(), &read.operands()), Some(read));
+    assert_eq!(Instruction::from_operands(signed.opcode(), &signed.operands()), Some(signed));
+    assert_eq!(Instruction::from_operands(signed.opcode(), &read.operands()), None);
+    assert_eq!(Instruction::from_operands(Instruction::Nop.opcode(), &read.operands()), None);
+}
+
+#[test]
+fn max_instruction_len_matches_table() {
+    let longest = INSTRUCTIONS.iter().map(|def| def.encoded_len(Some(DataType::OByte))).max();
+
+    assert_eq!(longest, Some(MAX_INSTRUCTION_LEN));
+}
+
+#[test]
+fn samples_cover_every_opcode() {
+    let samples = samples();
+
+    for def in INSTRUCTIONS {
+        assert!(samples.iter().any(|instruction| instruction.opcode() == def.opcode), "no sample for opcode {}", def.opcode);
+    }
+}
+
+#[test]
+fn encode_decode_round_trip() {
+    for instruction in samples() {
+        let bytes = instruction.encode();
+
+        assert_eq!(bytes.len(), instruction.encoded_len(), "{}", instruction);
+        assert_eq!(bytes[0], instruction.def().opcode);
+        assert_eq!(decode(&bytes), Ok((instruction, bytes.len())), "{}", instruction);
+
+        // any shorter slice is cut off
+        for len in 0..bytes.len() {
+            assert_eq!(decode(&bytes[..len]), Err(DecodeError::Truncated), "{}", instruction);
+        }
+    }
+}
+
+#[test]
+fn address_offset_points_at_address() {
+    for instruction in samples() {
+        if let Some(address) = instruction.jump_target() {
+            let offset = instruction.address_offset().unwrap();
+            let bytes = instruction.encode();
+
+            assert_eq!(bytes[offset..offset + 8], address.to_be_bytes(), "{}", instruction);
+        }
+    }
+}
+
+#[test]
+fn decode_rejects_bad_bytes() {
+    assert_eq!(decode(&[255]), Err(DecodeError::BadOpcode(255)));
+    assert_eq!(decode(&[2, 4, 0, 0]), Err(DecodeError::BadOperandType(4)));
+    assert_eq!(decode(&[9, 0, 16]), Err(DecodeError::BadRegister(16)));
+    assert_eq!(decode(&[13, 2, 0]), Err(DecodeError::BadCondition(2)));
+    assert_eq!(decode(&[30, 14, 0]), Err(DecodeError::BadCondition(14)));
+}
+
+#[test]
+fn emulator_executes_encoded_program() {
+    let program = [
+        Instruction::MoveValue { ty: DataType::Byte, register: 0, value: 40 },
+        Instruction::MoveValue { ty: DataType::QByte, register: 1, value: 2 },
+        Instruction::Add,
+        Instruction::WriteAddress { ty: DataType::DByte, register: 2, address: 1000 },
+        Instruction::ReadAddress { ty: DataType::Byte, register: 4, address: 1001 },
+        Instruction::PushRegister { ty: DataType::OByte, register: 4 },
+        Instruction::Pop { ty: DataType::OByte, register: 5 },
+        Instruction::MoveRegister { dst: 6, src: 5 },
+        Instruction::Halt
+    ];
+
+    let bin: Vec<u8> = program.iter().flat_map(Instruction::encode).collect();
+    let mut emulator = Emulator::new(&bin);
+
+    assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+    assert_eq!(emulator.register(2), 42);
+    assert_eq!(emulator.memory(1000..1002).as_deref(), Some(&[0, 42][..]));
+    assert_eq!(emulator.register(6), 42);
+    assert_eq!(emulator.register(COUNTER_REG), bin.len() as u64);
+    assert_eq!(emulator.register(STACK_REG), RAM_SIZE as u64 - 1);
+}
+
+#[test]
+fn emulator_steps_match_decoded_lengths() {
+    let program = [
+        Instruction::MoveValue { ty: DataType::OByte, register: 0, value: 7 },
+        // c is 0 so this doesn't jump
+        Instruction::JumpIf { condition: true, address: 0 },
+        Instruction::Nop,
+        Instruction::Halt
+    ];
+
+    let bin: Vec<u8> = program.iter().flat_map(Instruction::encode).collect();
+    let mut emulator = Emulator::new(&bin);
+
+    let mut pc = 0;
+    for instruction in program {
+        assert_eq!(emulator.register(COUNTER_REG), pc);
+        assert_eq!(decode(&bin[pc as usize..]).map(|(decoded, _)| decoded), Ok(instruction));
+
+        emulator.step().unwrap();
+        pc += instruction.encoded_len() as u64;
+    }
+
+    assert!(emulator.is_halted());
+}
+
+#[test]
+fn assembler_output_decodes_to_what_runs() {
+    let bin = assemble(include_str!("../test.asm")).unwrap().bin;
+
+    // decoding the whole image gives back the instructions in the source
+    let mut decoded = Vec::new();
+    let mut offset = 0;
+    while offset < bin.len() {
+        let (instruction, len) = decode(&bin[offset..]).unwrap();
+        decoded.push((offset as u64, instruction));
+        offset += len;
+    }
+
+    assert!(decoded.iter().any(|(_, instruction)| *instruction == Instruction::Halt));
+    assert!(decoded.iter().any(|(_, instruction)| *instruction == Instruction::JumpIf { condition: true, address: 0 }));
+
+    // and the emulator walks exactly those instruction boundaries
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(CONSOLE_BASE, CONSOLE_LEN, Box::new(Console::new(std::io::sink(), None)));
+    while !emulator.is_halted() {
+        let pc = emulator.register(COUNTER_REG);
+        assert!(decoded.iter().any(|(offset, _)| *offset == pc), "pc {} is not an instruction boundary", pc);
+
+        emulator.step().unwrap();
+    }
+
+    assert_eq!(emulator.register(6), 100);
+}
+
+#[test]
+fn assembler_patches_every_use_of_a_label() {
+    let bin = assemble("jump end\njump end true\n:end\nhalt\n").unwrap().bin;
+
+    let (first, first_len) = decode(&bin).unwrap();
+    let (second, _) = decode(&bin[first_len..]).unwrap();
+
+    assert_eq!(first, Instruction::Jump { address: 19 });
+    assert_eq!(second, Instruction::JumpIf { condition: true, address: 19 });
+}
+
+#[test]
+fn alu_forms_name_their_registers() {
+    let bin = assemble("
+        move byte e 7
+        move byte f 3
+        mul g e f
+        sub g g 1
+        add dbyte h g 1000
+        div i h f
+        less j f e
+        not k j
+        add
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+
+    assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+    assert_eq!(emulator.register(6), 20);
+    assert_eq!(emulator.register(7), 1020);
+    assert_eq!(emulator.register(8), 340);
+    assert_eq!(emulator.register(9), 1);
+    assert_eq!(emulator.register(10), !1);
+    // the legacy form still works on a & b into c
+    assert_eq!(emulator.register(2), 0);
+}
+
+#[test]
+fn alu_sets_flags_for_jumps() {
+    let run = |src: &str| {
+        let mut emulator = Emulator::new(&assemble(src).unwrap().bin);
+        assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+        emulator.flags()
+    };
+
+    // 1 - 2 borrows and goes negative without a signed overflow
+    let flags = run("move byte a 1\nsub b a 2\nhalt");
+    assert_eq!(flags, Flags { zero: false, carry: true, negative: true, overflow: false });
+    assert!(flags.test(FlagCondition::Less));
+    assert!(!flags.test(FlagCondition::Above));
+
+    // the largest signed number plus 1 overflows but doesn't carry
+    let flags = run("move obyte a 9223372036854775807\nadd b a 1\nhalt");
+    assert_eq!(flags, Flags { zero: false, carry: false, negative: true, overflow: true });
+    assert!(flags.test(FlagCondition::GreaterEqual));
+
+    // the legacy form sets them too
+    let flags = run("move obyte a 18446744073709551615\nmove byte b 1\nadd\nhalt");
+    assert_eq!(flags, Flags { zero: true, carry: true, negative: false, overflow: false });
+}
+
+#[test]
+fn flag_jumps_follow_compares() {
+    let bin = assemble("
+        :loop
+        add a a 1
+        less c a 10
+        jlt loop
+        jump end z
+        halt
+        :end
+        move byte b 1
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+
+    assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+    assert_eq!(emulator.register(0), 10);
+    assert_eq!(emulator.register(1), 1);
+}
+
+#[test]
+fn negative_alu_values_keep_their_sign() {
+    let bin = assemble("
+        move byte a 5
+        add b a -1
+        add obyte c a -1
+        less_signed d a -6
+        less_signed obyte e a -6
+        greater_signed f a -6
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+
+    assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+    assert_eq!(emulator.register(1), 4);
+    assert_eq!(emulator.register(2), 4);
+    assert_eq!(emulator.register(3), 0);
+    assert_eq!(emulator.register(4), 0);
+    assert_eq!(emulator.register(5), 1);
+}
+
+#[test]
+fn signed_instructions_treat_the_top_bit_as_the_sign() {
+    let bin = assemble("
+        move byte a 5
+        neg b a
+        less c b a
+        less_signed d b a
+        greater_signed e b -6
+        div_signed f b 2
+        rem_signed g b 2
+        rem h a 3
+        write byte b 1000
+        read sbyte i 1000
+        read byte j 1000
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+
+    assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+    assert_eq!(emulator.register(1) as i64, -5);
+    assert_eq!(emulator.register(2), 0);
+    assert_eq!(emulator.register(3), 1);
+    assert_eq!(emulator.register(4), 1);
+    assert_eq!(emulator.register(5) as i64, -2);
+    assert_eq!(emulator.register(6) as i64, -1);
+    assert_eq!(emulator.register(7), 2);
+    assert_eq!(emulator.register(8) as i64, -5);
+    assert_eq!(emulator.register(9), 251);
+}
+
+#[test]
+fn shifts_and_rotates() {
+    let bin = assemble("
+        move byte a 11
+        move byte b 4
+        shl c a b
+        shr d c 1
+        move obyte e -16
+        sar f e 2
+        shr g e 60
+        rol h a 60
+        ror i a 4
+        shl j a 64
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+
+    assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+    assert_eq!(emulator.register(2), 176);
+    assert_eq!(emulator.register(3), 88);
+    assert_eq!(emulator.register(5) as i64, -4);
+    assert_eq!(emulator.register(6), 15);
+    assert_eq!(emulator.register(7), 0xb000_0000_0000_0000);
+    assert_eq!(emulator.register(8), 0xb000_0000_0000_0000);
+    assert_eq!(emulator.register(9), 0);
+    // shifting left by 64 pushes bit 0 out last
+    assert!(emulator.flags().zero && emulator.flags().carry);
+}
+
+#[test]
+fn cycles_add_up_from_the_table() {
+    let program = [
+        Instruction::MoveValue { ty: DataType::Byte, register: 0, value: 6 },
+        Instruction::AluValue { op: AluOp::Div, ty: DataType::Byte, dst: 1, src: 0, value: 2 },
+        Instruction::WriteAddress { ty: DataType::Byte, register: 1, address: 1000 },
+        Instruction::Halt
+    ];
+
+    let bin: Vec<u8> = program.iter().flat_map(Instruction::encode).collect();
+    let expected: u64 = program.iter().map(|instruction| instruction.def().cycles).sum();
+
+    let mut emulator = Emulator::new(&bin);
+    assert_eq!(emulator.run(Budget::Cycles(2)), RunOutcome::CycleLimit);
+    assert_eq!(emulator.cycles(), 11);
+
+    assert_eq!(emulator.run(Budget::Unlimited), RunOutcome::Halted);
+    assert_eq!(emulator.cycles(), expected);
+}
+
+#[test]
+fn call_pushes_the_return_address_and_ret_pops_it() {
+    let bin = assemble("
+        move byte a 3
+        call double
+        call double
+        halt
+
+        :double
+        move b a
+        add
+        move a c
+        ret
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+
+    assert_eq!(emulator.run(Budget::Steps(100)), RunOutcome::Halted);
+    assert_eq!(emulator.register(0), 12);
+    assert_eq!(emulator.register(STACK_REG), RAM_SIZE as u64 - 1);
+}
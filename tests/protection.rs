@@ -0,0 +1,120 @@
+use c64::{
+    assembler::assemble,
+    emulator::{Budget, Emulator, Fault, FaultKind, RunOutcome, RAM_SIZE},
+    protection::{Access, Permissions}
+};
+
+#[test]
+fn writing_to_read_only_code_faults() {
+    let bin = assemble("
+        move byte a 1
+        write obyte a 3
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.protect(0..bin.len() as u64, Permissions::READ | Permissions::EXECUTE);
+
+    let outcome = emulator.run(Budget::Steps(10));
+    let RunOutcome::Fault(fault) = outcome else {
+        panic!("expected a fault, got {:?}", outcome);
+    };
+
+    assert_eq!(fault.kind, FaultKind::Protection { address: 3, access: Access::Write });
+    // the pc is left on the write
+    assert_eq!(fault.pc, emulator.register(14));
+    assert_eq!(emulator.memory(0..bin.len() as u64).unwrap(), bin.as_slice());
+    assert_eq!(fault.to_string(), format!("write denied for address 3 at address {} (opcode {})", fault.pc, fault.opcode.unwrap()));
+}
+
+#[test]
+fn executing_data_faults() {
+    let bin = assemble("
+        jump 1000
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.protect(1000..2000, Permissions::READ | Permissions::WRITE);
+
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Fault(Fault {
+        pc: 1000,
+        opcode: Some(0),
+        kind: FaultKind::Protection { address: 1000, access: Access::Execute }
+    }));
+}
+
+#[test]
+fn later_regions_override_earlier_ones() {
+    let bin = assemble("
+        move byte a 7
+        write byte a 1001
+        read byte b 1001
+        read byte c 1010
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.protect(0..RAM_SIZE as u64, Permissions::READ | Permissions::EXECUTE);
+    emulator.protect(1000..1010, Permissions::READ | Permissions::WRITE);
+    emulator.protect(1010..1011, Permissions::NONE);
+
+    let outcome = emulator.run(Budget::Steps(10));
+    let RunOutcome::Fault(fault) = outcome else {
+        panic!("expected a fault, got {:?}", outcome);
+    };
+
+    assert_eq!(emulator.register(1), 7);
+    assert_eq!(fault.kind, FaultKind::Protection { address: 1010, access: Access::Read });
+}
+
+#[test]
+fn pushing_onto_a_read_only_stack_faults() {
+    let bin = assemble("
+        push byte a
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.protect(RAM_SIZE as u64 - 100..RAM_SIZE as u64, Permissions::READ);
+
+    assert_eq!(emulator.run(Budget::Steps(10)), RunOutcome::Fault(Fault {
+        pc: 0,
+        opcode: Some(bin[0]),
+        kind: FaultKind::Protection { address: RAM_SIZE as u64 - 1, access: Access::Write }
+    }));
+}
+
+#[test]
+fn permissions_parse_and_print() {
+    assert_eq!(Permissions::from_name("rx"), Some(Permissions::READ | Permissions::EXECUTE));
+    assert_eq!(Permissions::from_name("xwr"), Some(Permissions::ALL));
+    assert_eq!(Permissions::from_name("-"), Some(Permissions::NONE));
+    assert_eq!(Permissions::from_name("rq"), None);
+    assert_eq!(Permissions::from_name(""), None);
+
+    assert_eq!((Permissions::EXECUTE | Permissions::READ).to_string(), "rx");
+    assert_eq!(Permissions::NONE.to_string(), "-");
+}
+
+#[test]
+fn loaded_code_can_be_made_read_only() {
+    let bin = assemble("
+        move byte a 0
+        write byte a 0 ; would turn the first instruction into a nop
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::with_ram_size(&[], RAM_SIZE as u64);
+    emulator.load_at_with(0, &bin, Permissions::READ | Permissions::EXECUTE).unwrap();
+
+    let outcome = emulator.run(Budget::Steps(10));
+    let RunOutcome::Fault(fault) = outcome else {
+        panic!("expected a fault, got {:?}", outcome);
+    };
+
+    assert_eq!(fault.kind, FaultKind::Protection { address: 0, access: Access::Write });
+    assert_eq!(emulator.memory(0..bin.len() as u64).unwrap(), bin.as_slice());
+
+    // the rest of ram is still writable
+    assert_eq!(emulator.protection().permissions(bin.len() as u64), Permissions::ALL);
+}
@@ -0,0 +1,110 @@
+use c64::{
+    assembler::assemble,
+    emulator::{Budget, Emulator, FaultKind, RunOutcome, RAM_SIZE, STACK_REG},
+    protection::{Access, Permissions}
+};
+
+fn fault_kind(outcome: RunOutcome) -> FaultKind {
+    match outcome {
+        RunOutcome::Fault(fault) => fault.kind,
+        outcome => panic!("expected a fault, got {:?}", outcome)
+    }
+}
+
+#[test]
+fn pushing_past_the_limit_overflows() {
+    let bin = assemble("
+        :loop
+        push obyte a
+        jump loop
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.set_stack(1000..1100);
+    assert_eq!(emulator.register(STACK_REG), 1099);
+
+    assert_eq!(fault_kind(emulator.run(Budget::Steps(1000))), FaultKind::StackOverflow);
+    // 12 obytes fit in 100 bytes, the 13th doesn't
+    assert_eq!(emulator.register(STACK_REG), 1099 - 12 * 8);
+    assert_eq!(emulator.memory(0..bin.len() as u64).unwrap(), bin.as_slice());
+}
+
+#[test]
+fn popping_past_the_base_underflows() {
+    let bin = assemble("
+        push dbyte a
+        pop byte b
+        pop byte c
+        pop byte d
+        halt
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    emulator.set_register(0, 0x1234);
+
+    assert_eq!(fault_kind(emulator.run(Budget::Steps(10))), FaultKind::StackUnderflow);
+    assert_eq!(emulator.register(1), 0x34);
+    assert_eq!(emulator.register(2), 0x12);
+    assert_eq!(emulator.register(STACK_REG), RAM_SIZE as u64 - 1);
+}
+
+#[test]
+fn the_stack_never_grows_into_the_program() {
+    let bin = assemble("
+        :loop
+        push byte a
+        jump loop
+    ").unwrap().bin;
+
+    let mut emulator = Emulator::new(&bin);
+    // a stack whose limit is below the end of the program
+    emulator.set_stack(0..bin.len() as u64 + 3);
+
+    assert_eq!(fault_kind(emulator.run(Budget::Steps(1000))), FaultKind::StackOverflow);
+    assert_eq!(emulator.register(STACK_REG), bin.len() as u64 - 1);
+    assert_eq!(emulator.memory(0..bin.len() as u64).unwrap(), bin.as_slice());
+}
+
+#[test]
+fn a_stack_pointer_moved_outside_the_stack_can_push_or_pop_nothing() {
+    let push = assemble("push byte a").unwrap().bin;
+    let pop = assemble("pop byte a").unwrap().bin;
+
+    // above the top, where the value would land past the end of the stack
+    let mut emulator = Emulator::new(&push);
+    emulator.set_stack(1000..1100);
+    emulator.set_register(STACK_REG, 1100);
+    assert_eq!(fault_kind(emulator.run(Budget::Steps(10))), FaultKind::StackOverflow);
+    assert_eq!(emulator.memory(1100..1101).as_deref(), Some(&[0][..]));
+
+    let mut emulator = Emulator::new(&pop);
+    emulator.set_stack(1000..1100);
+    emulator.set_register(STACK_REG, 1200);
+    assert_eq!(fault_kind(emulator.run(Budget::Steps(10))), FaultKind::StackUnderflow);
+
+    // below the limit, where popping would read what was never pushed
+    let mut emulator = Emulator::new(&pop);
+    emulator.set_stack(1000..1100);
+    emulator.set_register(STACK_REG, 900);
+    assert_eq!(fault_kind(emulator.run(Budget::Steps(10))), FaultKind::StackUnderflow);
+    assert_eq!(emulator.register(STACK_REG), 900);
+}
+
+#[test]
+fn popping_memory_that_cant_be_read_reports_why() {
+    let bin = assemble("pop byte a").unwrap().bin;
+
+    // the stack reaches past the end of ram
+    let mut emulator = Emulator::new(&bin);
+    emulator.set_stack(RAM_SIZE as u64 - 100..RAM_SIZE as u64 + 100);
+    emulator.set_register(STACK_REG, RAM_SIZE as u64 + 10);
+    assert_eq!(fault_kind(emulator.run(Budget::Steps(10))), FaultKind::BadAddress(RAM_SIZE as u64 + 11));
+
+    // the stack is write only
+    let mut emulator = Emulator::new(&bin);
+    emulator.set_stack(1000..1100);
+    emulator.protect(1000..1100, Permissions::WRITE);
+    emulator.set_register(STACK_REG, 1050);
+    assert_eq!(fault_kind(emulator.run(Budget::Steps(10))), FaultKind::Protection { address: 1051, access: Access::Read });
+    assert_eq!(emulator.register(STACK_REG), 1050);
+}
@@ -0,0 +1,157 @@
+use std::sync::mpsc;
+
+use c64::{
+    assembler::assemble,
+    devices::console::{Console, Input, CONSOLE_BASE, CONSOLE_LEN},
+    emulator::{Budget, Emulator, FaultKind, RunOutcome},
+    syscalls
+};
+
+mod common;
+
+use common::SharedOutput;
+
+/// Input that ends after `bytes`
+fn input(bytes: &[u8]) -> Input {
+    let (sender, receiver) = mpsc::channel();
+    for byte in bytes {
+        sender.send(*byte).unwrap();
+    }
+
+    Input::new(Some(receiver))
+}
+
+fn run(src: &str, bytes: &'static [u8]) -> (Emulator, RunOutcome, Vec<u8>) {
+    let output = SharedOutput::default();
+    let mut emulator = Emulator::new(&assemble(src).unwrap().bin);
+    syscalls::install(&mut emulator, output.clone(), input(bytes));
+
+    let outcome = emulator.run(Budget::Steps(1000));
+    let output = output.0.borrow().clone();
+
+    (emulator, outcome, output)
+}
+
+#[test]
+fn prints_numbers_and_strings() {
+    let (_, outcome, output) = run("
+        move obyte a -42
+        syscall 0
+        move byte a 24 ; where the bytes after halt start
+        move byte b 4
+        syscall 1
+        halt
+        byte 32
+        byte 104
+        byte 105
+        byte 10
+    ", b"");
+
+    assert_eq!(outcome, RunOutcome::Halted);
+    assert_eq!(output, b"-42 hi\n");
+}
+
+#[test]
+fn reads_lines_until_the_input_ends() {
+    let (emulator, outcome, _) = run("
+        move dbyte a 1000
+        move byte b 3
+        syscall 2
+        move g a
+        move dbyte a 1000
+        move byte b 3
+        syscall 2
+        move h a
+        syscall 2
+        halt
+    ", b"hello\nab\n");
+
+    assert_eq!(outcome, RunOutcome::Halted);
+    assert_eq!(emulator.register(6), 3);
+    assert_eq!(emulator.register(7), 2);
+    assert_eq!(emulator.register(0), u64::MAX);
+    assert_eq!(emulator.memory(1000..1003).as_deref(), Some(&b"abl"[..]));
+}
+
+#[test]
+fn exit_stops_with_a_code() {
+    let (emulator, outcome, _) = run("
+        move byte a 3
+        syscall 3
+        move byte a 9
+        halt
+    ", b"");
+
+    assert_eq!(outcome, RunOutcome::Halted);
+    assert_eq!(emulator.exit_code(), 3);
+    assert_eq!(emulator.register(0), 3);
+}
+
+#[test]
+fn unknown_syscalls_fault() {
+    let (_, outcome, _) = run("syscall 200\nhalt\n", b"");
+
+    match outcome {
+        RunOutcome::Fault(fault) => assert_eq!(fault.kind, FaultKind::BadSyscall(200)),
+        outcome => panic!("expected a fault, got {:?}", outcome)
+    }
+}
+
+#[test]
+fn printing_a_huge_string_faults_instead_of_crashing() {
+    let (_, outcome, _) = run("
+        move obyte a 100
+        move obyte b 0xffffffffffffffff
+        syscall 1
+        halt
+    ", b"");
+
+    match outcome {
+        RunOutcome::Fault(fault) => assert_eq!(fault.kind, FaultKind::BadAddress(100)),
+        outcome => panic!("expected a fault, got {:?}", outcome)
+    }
+
+    // fits in an address but runs off the end of ram
+    let (_, outcome, output) = run("
+        move obyte a 100
+        move obyte b 0xffffff
+        syscall 1
+        halt
+    ", b"");
+
+    match outcome {
+        RunOutcome::Fault(fault) => assert!(matches!(fault.kind, FaultKind::BadAddress(_)), "{:?}", fault),
+        outcome => panic!("expected a fault, got {:?}", outcome)
+    }
+    assert!(output.len() < 320_000);
+}
+
+#[test]
+fn console_and_read_line_share_the_input() {
+    // wired up the way the c64 binary does it
+    let bin = assemble("
+        :wait
+        read byte a 0xfffffffff0000001 ; console status
+        and b a 1
+        jz wait
+        read byte g 0xfffffffff0000000
+        move dbyte a 1000
+        move byte b 10
+        syscall 2
+        move h a
+        read byte i 0xfffffffff0000001 ; the line took everything
+        halt
+    ").unwrap().bin;
+
+    let output = SharedOutput::default();
+    let shared = input(b"Xhello\n");
+    let mut emulator = Emulator::new(&bin);
+    emulator.bus_mut().map(CONSOLE_BASE, CONSOLE_LEN, Box::new(Console::with_input(output.clone(), shared.clone())));
+    syscalls::install(&mut emulator, output, shared);
+
+    assert_eq!(emulator.run(Budget::Steps(1000)), RunOutcome::Halted);
+    assert_eq!(emulator.register(6), b'X' as u64);
+    assert_eq!(emulator.register(7), 5);
+    assert_eq!(emulator.memory(1000..1005).as_deref(), Some(&b"hello"[..]));
+    assert_eq!(emulator.register(8), 2);
+}
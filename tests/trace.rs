@@ -0,0 +1,137 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc
+};
+
+use c64::{
+    assembler::assemble,
+    bus::Device,
+    emulator::{Budget, Emulator, RAM_SIZE, STACK_REG},
+    trace::{MemoryWrite, RegisterChange, TraceEvent, TraceFormat, TraceSink, TraceWriter}
+};
+
+/// Keeps every event so a test can look at them after the run
+struct Recorder(Rc<RefCell<Vec<TraceEvent>>>);
+
+impl TraceSink for Recorder {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.0.borrow_mut().push(event.clone());
+    }
+}
+
+fn run(source: &str, setup: impl FnOnce(&mut Emulator)) -> Vec<TraceEvent> {
+    let bin = assemble(source).unwrap().bin;
+    let events = Rc::new(RefCell::new(Vec::new()));
+
+    let mut emulator = Emulator::new(&bin);
+    setup(&mut emulator);
+    emulator.set_tracer(Some(Box::new(Recorder(events.clone()))));
+    emulator.run(Budget::Steps(100));
+
+    events.take()
+}
+
+fn write(events: &[TraceEvent], format: TraceFormat, range: Option<std::ops::Range<u64>>) -> String {
+    let mut out = TraceWriter::new(Vec::new(), format, range);
+    for event in events {
+        out.trace(event);
+    }
+
+    String::from_utf8(out.into_inner()).unwrap()
+}
+
+const PROGRAM: &str = "
+    move byte a 5
+    write byte a 100
+    halt
+";
+
+#[test]
+fn text_lists_the_instruction_and_what_changed() {
+    let text = write(&run(PROGRAM, |_| {}), TraceFormat::Text, None);
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("       0  02 00 00 05"), "{}", lines[0]);
+    assert!(lines[0].contains("move byte a 5"), "{}", lines[0]);
+    assert!(lines[0].ends_with("  a: 0 -> 5"), "{}", lines[0]);
+    assert!(lines[1].contains("write byte a 100"), "{}", lines[1]);
+    assert!(lines[1].ends_with("  [100]: 05"), "{}", lines[1]);
+    assert!(lines[2].trim_end().ends_with("halt"), "{}", lines[2]);
+}
+
+#[test]
+fn json_is_one_object_per_step() {
+    let json = write(&run(PROGRAM, |_| {}), TraceFormat::Json, None);
+    let lines: Vec<&str> = json.lines().collect();
+
+    assert_eq!(lines, [
+        "{\"pc\":0,\"bytes\":[2,0,0,5],\"instruction\":\"move byte a 5\",\"registers\":{\"a\":5},\"memory\":[]}",
+        "{\"pc\":4,\"bytes\":[5,0,0,0,0,0,0,0,0,0,100],\"instruction\":\"write byte a 100\",\"registers\":{},\"memory\":[{\"address\":100,\"bytes\":[5]}]}",
+        "{\"pc\":15,\"bytes\":[24],\"instruction\":\"halt\",\"registers\":{},\"memory\":[]}"
+    ]);
+}
+
+#[test]
+fn faults_are_written_with_the_step() {
+    let json = write(&run("div a a b", |_| {}), TraceFormat::Json, None);
+
+    assert!(json.trim_end().ends_with(",\"fault\":\"divide by zero\"}"), "{}", json);
+}
+
+#[test]
+fn range_leaves_out_steps_outside_it() {
+    let text = write(&run(PROGRAM, |_| {}), TraceFormat::Text, Some(4..15));
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("write byte a 100"), "{}", lines[0]);
+}
+
+/// Holds its interrupt line up until the program writes to it
+struct Line(Rc<Cell<bool>>);
+
+impl Device for Line {
+    fn read(&mut self, _offset: u64) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _offset: u64, _value: u8) {
+        self.0.set(false);
+    }
+
+    fn irq(&self) -> bool {
+        self.0.get()
+    }
+}
+
+#[test]
+fn interrupt_entry_shows_up_in_the_step_that_took_it() {
+    let source = "
+        vectors table
+        enable_interrupts
+        nop
+        halt
+
+        :table
+        obyte handler
+
+        :handler
+        write byte a 0xfffffffff0000000
+        iret
+    ";
+
+    let events = run(source, |emulator| {
+        emulator.bus_mut().map_with_irq(0xffff_ffff_f000_0000, 1, 0, Box::new(Line(Rc::new(Cell::new(true)))));
+    });
+
+    // the interrupt is taken right after enable_interrupts, before the nop at 10
+    let entry = &events[2];
+    let sp = RAM_SIZE as u64 - 1;
+    assert!(entry.instruction_text().starts_with("write byte a"), "{:?}", entry);
+    assert_eq!(entry.registers, [RegisterChange { register: STACK_REG, old: sp, new: sp - 9 }]);
+    assert_eq!(entry.memory[..2], [
+        MemoryWrite { address: sp - 7, bytes: 10u64.to_le_bytes().to_vec() },
+        MemoryWrite { address: sp - 8, bytes: vec![0] }
+    ]);
+}